@@ -1,7 +1,8 @@
+use crate::utils::RetryPolicy;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     pub upload: UploadConfig,
     pub workflow: WorkflowConfig,
@@ -25,12 +26,32 @@ pub struct WorkflowConfig {
     pub max_concurrent_workflows: usize,
     pub timeout_seconds: u64,
     pub retry_attempts: u32,
+    #[serde(default = "default_max_parallel_steps")]
+    pub max_parallel_steps: usize,
+    #[serde(default = "default_kill_grace_period_seconds")]
+    pub kill_grace_period_seconds: u64,
+    /// Deadline for a whole workflow run unless the workflow sets its own
+    /// `timeout_seconds`; unset means runs are only bounded by their steps.
+    #[serde(default)]
+    pub workflow_timeout_seconds: Option<u64>,
+    /// Encrypted file read by `{"encrypted": "<name>"}` secrets, see `--seal-secrets`.
+    #[serde(default)]
+    pub secrets_file: Option<PathBuf>,
+    /// Environment variable holding the passphrase of `secrets_file`.
+    #[serde(default = "default_secrets_key_env")]
+    pub secrets_key_env: String,
+    /// Default backoff and retry filters for steps without their own policy.
+    #[serde(default)]
+    pub retry_policy: RetryPolicy,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemConfig {
     pub temp_dir: PathBuf,
     pub cache_dir: PathBuf,
+    /// Bytes the step cache may use before its least recently used entries are evicted.
+    #[serde(default = "default_cache_max_size")]
+    pub cache_max_size: u64,
     pub max_memory_usage: usize,
     pub cpu_limit: f64,
 }
@@ -49,16 +70,13 @@ impl Config {
             .add_source(config::Environment::with_prefix("AUTOMATION"))
             .build()?;
 
-        Ok(settings.try_deserialize()?)
-    }
-
-    pub fn default() -> Self {
-        Self {
-            upload: UploadConfig::default(),
-            workflow: WorkflowConfig::default(),
-            system: SystemConfig::default(),
-            logging: LoggingConfig::default(),
-        }
+        let config: Self = settings.try_deserialize()?;
+        config
+            .workflow
+            .retry_policy
+            .validate()
+            .map_err(|e| anyhow::anyhow!("Invalid workflow.retry_policy in {}: {}", path, e))?;
+        Ok(config)
     }
 }
 
@@ -90,15 +108,38 @@ impl Default for WorkflowConfig {
             max_concurrent_workflows: 4,
             timeout_seconds: 3600, // 1 hour
             retry_attempts: 3,
+            max_parallel_steps: default_max_parallel_steps(),
+            kill_grace_period_seconds: default_kill_grace_period_seconds(),
+            workflow_timeout_seconds: None,
+            secrets_file: None,
+            secrets_key_env: default_secrets_key_env(),
+            retry_policy: RetryPolicy::default(),
         }
     }
 }
 
+fn default_max_parallel_steps() -> usize {
+    8
+}
+
+fn default_kill_grace_period_seconds() -> u64 {
+    10
+}
+
+fn default_secrets_key_env() -> String {
+    "ORCHESTRATOR_SECRETS_KEY".to_string()
+}
+
+fn default_cache_max_size() -> u64 {
+    1024 * 1024 * 1024 // 1GB
+}
+
 impl Default for SystemConfig {
     fn default() -> Self {
         Self {
             temp_dir: PathBuf::from("./temp"),
             cache_dir: PathBuf::from("./cache"),
+            cache_max_size: default_cache_max_size(),
             max_memory_usage: 1024 * 1024 * 1024, // 1GB
             cpu_limit: 0.8, // 80%
         }
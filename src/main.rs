@@ -1,10 +1,11 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use rust_automation_orchestrator::{
     config::Config,
     orchestrator::AutomationOrchestrator,
     upload::UploadManager,
-    workflow::WorkflowEngine,
+    workflow::{format, inputs, secrets, ApprovalDecision, StepCache, WorkflowEngine},
 };
+use std::{collections::HashMap, path::PathBuf};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -13,10 +14,34 @@ struct Args {
     #[clap(short, long, default_value = "config.yaml")]
     config: String,
 
-    /// Workflow file to execute
+    /// Workflow to execute: a file, or a registered workflow as NAME or NAME@VERSION
     #[clap(short, long)]
     workflow: Option<String>,
 
+    /// Set a workflow input, e.g. --set input_file=report.pdf (repeatable)
+    #[clap(long = "set", value_name = "KEY=VALUE", value_parser = parse_assignment)]
+    set: Vec<(String, String)>,
+
+    /// JSON file with workflow input values; --set takes precedence
+    #[clap(long, value_name = "FILE")]
+    inputs: Option<PathBuf>,
+
+    /// Print what the workflow would run instead of running it
+    #[clap(long, requires = "workflow")]
+    dry_run: bool,
+
+    /// Print the --dry-run plan as JSON
+    #[clap(long, requires = "dry-run")]
+    json: bool,
+
+    /// Resume a failed or interrupted workflow execution by ID
+    #[clap(short, long)]
+    resume: Option<uuid::Uuid>,
+
+    /// Encrypt a JSON object of secret values into the configured secrets_file
+    #[clap(long, value_name = "FILE")]
+    seal_secrets: Option<PathBuf>,
+
     /// Upload directory
     #[clap(short, long)]
     upload: Option<String>,
@@ -24,6 +49,50 @@ struct Args {
     /// Enable verbose logging
     #[clap(short, long)]
     verbose: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check workflow files without running them; exits non-zero when problems are found
+    Validate {
+        /// Workflow files to check; defaults to every workflow in workflow_dir
+        files: Vec<String>,
+
+        /// Print the problems as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// List every version of each workflow in workflow_dir
+    List {
+        /// Print the registry as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Approve an Approval step that is waiting in a running execution
+    Approve(Decision),
+    /// Reject an Approval step that is waiting in a running execution
+    Reject(Decision),
+    /// Rewrite a workflow in another format (JSON, YAML or TOML, by file extension)
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct Decision {
+    execution_id: uuid::Uuid,
+    step_id: String,
+
+    /// Who decides; defaults to $USER
+    #[clap(long)]
+    approver: Option<String>,
+
+    #[clap(long)]
+    comment: Option<String>,
 }
 
 #[tokio::main]
@@ -42,15 +111,120 @@ async fn main() -> anyhow::Result<()> {
     let config = Config::load(&args.config)?;
     tracing::info!("Configuration loaded from {}", args.config);
 
+    if let Some(plaintext_path) = &args.seal_secrets {
+        secrets::seal_file(plaintext_path, &config.workflow)?;
+        tracing::info!("Sealed {} into the secrets file", plaintext_path.display());
+    }
+
     // Initialize components
     let upload_manager = UploadManager::new(config.upload.clone());
-    let workflow_engine = WorkflowEngine::new(config.workflow.clone());
+    let workflow_engine = WorkflowEngine::new(config.workflow.clone())
+        .with_upload_manager(UploadManager::new(config.upload.clone()))
+        .with_step_cache(StepCache::new(config.system.cache_dir.clone(), config.system.cache_max_size));
     let orchestrator = AutomationOrchestrator::new(config, upload_manager, workflow_engine);
 
+    if let Some(Command::Convert { input, output }) = &args.command {
+        format::convert(input, output)?;
+        tracing::info!("Converted {} to {}", input.display(), output.display());
+        return Ok(());
+    }
+
+    if let Some(Command::Approve(decision) | Command::Reject(decision)) = &args.command {
+        let outcome = match &args.command {
+            Some(Command::Approve(_)) => ApprovalDecision::Approved,
+            _ => ApprovalDecision::Rejected,
+        };
+        let approver = match &decision.approver {
+            Some(approver) => approver.clone(),
+            None => std::env::var("USER").map_err(|_| anyhow::anyhow!("Pass --approver; $USER is not set"))?,
+        };
+        orchestrator.decide_approval(
+            decision.execution_id,
+            &decision.step_id,
+            outcome,
+            &approver,
+            decision.comment.as_deref(),
+        )?;
+        println!("Step {} of execution {}: {:?} by {}", decision.step_id, decision.execution_id, outcome, approver);
+        return Ok(());
+    }
+
+    if let Some(Command::List { json }) = &args.command {
+        let registry = orchestrator.workflow_registry().await?;
+        if *json {
+            println!("{}", serde_json::to_string_pretty(&registry)?);
+        } else {
+            for (name, versions) in &registry.workflows {
+                let versions: Vec<String> = versions.iter().map(|workflow| workflow.version.to_string()).collect();
+                println!("{}: {}", name, versions.join(", "));
+            }
+            for issue in &registry.issues {
+                println!("skipped {}", issue);
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Validate { files, json }) = &args.command {
+        let issues = orchestrator.validate_workflows(files).await?;
+        if *json {
+            println!("{}", serde_json::to_string_pretty(&issues)?);
+        } else {
+            for issue in &issues {
+                println!("{}", issue);
+            }
+            println!("{} problem(s) found", issues.len());
+        }
+        if !issues.is_empty() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Execute workflow if specified
     if let Some(workflow_path) = args.workflow {
+        let mut workflow_inputs = match &args.inputs {
+            Some(path) => inputs::load_inputs_file(path)?,
+            None => HashMap::new(),
+        };
+        workflow_inputs.extend(args.set);
+
+        if args.dry_run {
+            let plan = orchestrator.plan_workflow(&workflow_path, &workflow_inputs).await?;
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&plan)?);
+            } else {
+                print!("{}", plan);
+            }
+            return Ok(());
+        }
+
         tracing::info!("Executing workflow: {}", workflow_path);
-        orchestrator.execute_workflow(&workflow_path).await?;
+        let run = orchestrator.execute_workflow(&workflow_path, &workflow_inputs);
+        tokio::pin!(run);
+        tokio::select! {
+            result = &mut run => result?,
+            _ = tokio::signal::ctrl_c() => {
+                tracing::warn!("Interrupted, cancelling workflow");
+                orchestrator.cancel_workflows();
+                run.await?;
+            }
+        }
+    }
+
+    // Resume an earlier execution if specified
+    if let Some(execution_id) = args.resume {
+        tracing::info!("Resuming workflow execution: {}", execution_id);
+        let run = orchestrator.resume_workflow(execution_id);
+        tokio::pin!(run);
+        tokio::select! {
+            result = &mut run => result?,
+            _ = tokio::signal::ctrl_c() => {
+                tracing::warn!("Interrupted, cancelling workflow");
+                orchestrator.cancel_workflows();
+                run.await?;
+            }
+        }
     }
 
     // Handle upload if specified
@@ -62,3 +236,7 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Automation Orchestrator completed successfully");
     Ok(())
 }
+
+fn parse_assignment(assignment: &str) -> Result<(String, String), String> {
+    inputs::parse_assignment(assignment).map_err(|e| e.to_string())
+}
@@ -1,7 +1,14 @@
-use crate::{config::Config, upload::UploadManager, workflow::WorkflowEngine};
+use crate::{
+    config::Config,
+    upload::UploadManager,
+    workflow::{
+        ApprovalDecision, ApprovalRequest, ExecutionPlan, ValidationIssue, WorkflowEngine, WorkflowExecution,
+        WorkflowRegistry,
+    },
+};
 use anyhow::Result;
 use dashmap::DashMap;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 use tokio::sync::Semaphore;
 use tracing::{error, info, warn};
 use uuid::Uuid;
@@ -103,7 +110,59 @@ impl AutomationOrchestrator {
         result.map(|_| ())
     }
 
-    pub async fn execute_workflow(&self, workflow_path: &str) -> Result<()> {
+    /// Runs a workflow given by file path, or by registry name as `name` (the
+    /// latest version) or `name@version`.
+    pub async fn execute_workflow(&self, workflow: &str, inputs: &HashMap<String, String>) -> Result<()> {
+        let workflow_path = self.workflow_engine.resolve_workflow(workflow).await?;
+        self.run_workflow_task(self.workflow_engine.execute_workflow_with_inputs(&workflow_path, inputs))
+            .await
+    }
+
+    /// What `execute_workflow` would do, without running anything.
+    pub async fn plan_workflow(&self, workflow: &str, inputs: &HashMap<String, String>) -> Result<ExecutionPlan> {
+        let workflow_path = self.workflow_engine.resolve_workflow(workflow).await?;
+        self.workflow_engine.plan(&workflow_path, inputs).await
+    }
+
+    pub async fn workflow_registry(&self) -> Result<WorkflowRegistry> {
+        self.workflow_engine.registry().await
+    }
+
+    /// Problems in the given workflow files, or in every workflow when none are given.
+    pub async fn validate_workflows(&self, workflow_paths: &[String]) -> Result<Vec<ValidationIssue>> {
+        if workflow_paths.is_empty() {
+            return self.workflow_engine.validate_all_workflows().await;
+        }
+        let mut issues = Vec::new();
+        for workflow_path in workflow_paths {
+            issues.extend(self.workflow_engine.validate_workflow(workflow_path).await?);
+        }
+        Ok(issues)
+    }
+
+    /// Approves or rejects a waiting `Approval` step, recording who decided and why.
+    pub fn decide_approval(
+        &self,
+        execution_id: Uuid,
+        step_id: &str,
+        decision: ApprovalDecision,
+        approver: &str,
+        comment: Option<&str>,
+    ) -> Result<ApprovalRequest> {
+        self.workflow_engine
+            .decide_approval(execution_id, step_id, decision, approver, comment)
+    }
+
+    pub async fn resume_workflow(&self, execution_id: Uuid) -> Result<()> {
+        info!("Resuming workflow execution {}", execution_id);
+        self.run_workflow_task(self.workflow_engine.resume(execution_id))
+            .await
+    }
+
+    async fn run_workflow_task<F>(&self, run: F) -> Result<()>
+    where
+        F: std::future::Future<Output = Result<WorkflowExecution>>,
+    {
         let task_id = Uuid::new_v4();
         let task_info = TaskInfo {
             id: task_id,
@@ -126,7 +185,7 @@ impl AutomationOrchestrator {
             task.started_at = Some(chrono::Utc::now());
         }
 
-        let result = self.workflow_engine.execute_workflow(workflow_path).await;
+        let result = run.await;
 
         // Update task status based on result
         if let Some(mut task) = self.active_tasks.get_mut(&task_id) {
@@ -147,6 +206,16 @@ impl AutomationOrchestrator {
         result.map(|_| ())
     }
 
+    /// Cancels every running workflow execution, e.g. on Ctrl-C. Their records
+    /// are finalized as `Cancelled` once the running steps have been stopped.
+    pub fn cancel_workflows(&self) {
+        self.workflow_engine.cancel_all_executions();
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
     pub fn get_task_status(&self, task_id: Uuid) -> Option<TaskInfo> {
         self.active_tasks.get(&task_id).map(|task| task.clone())
     }
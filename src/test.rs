@@ -1,6 +1,102 @@
 #[cfg(test)]
 mod tests {
-    use crate::{config::Config, upload::UploadManager, workflow::WorkflowEngine};
+    use crate::{
+        config::{Config, WorkflowConfig},
+        upload::UploadManager,
+        workflow::{
+            format, secrets, ApprovalDecision, ExecutionStatus, PlanDecision, RetryPolicy, StepCache, Templated,
+            WorkflowEngine,
+        },
+    };
+    use std::path::PathBuf;
+
+    /// A scratch directory for one test, deleted when it goes out of scope.
+    struct TempDir(PathBuf);
+
+    impl std::ops::Deref for TempDir {
+        type Target = PathBuf;
+
+        fn deref(&self) -> &PathBuf {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn temp_workflow_dir() -> TempDir {
+        let dir = std::env::temp_dir().join(format!("workflow_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// Writes `workflow.json` with `steps`, merging `extra` into its top-level
+    /// fields; nested objects such as `metadata` are merged key by key.
+    fn write_workflow(dir: &std::path::Path, steps: serde_json::Value, extra: serde_json::Value) -> String {
+        let mut workflow = serde_json::json!({
+            "id": uuid::Uuid::new_v4(),
+            "name": "Test Workflow",
+            "description": null,
+            "version": "1.0.0",
+            "created_at": "2024-01-01T00:00:00Z",
+            "steps": steps,
+            "variables": {},
+            "metadata": {
+                "author": "test",
+                "tags": [],
+                "priority": "Normal",
+                "estimated_duration": null,
+                "resource_requirements": { "cpu_cores": 1, "memory_mb": 64, "disk_space_mb": 1 }
+            }
+        });
+        merge(&mut workflow, extra);
+        let path = dir.join("workflow.json");
+        std::fs::write(&path, workflow.to_string()).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn merge(target: &mut serde_json::Value, extra: serde_json::Value) {
+        match (target, extra) {
+            (serde_json::Value::Object(target), serde_json::Value::Object(extra)) => {
+                for (key, value) in extra {
+                    merge(target.entry(key).or_insert(serde_json::Value::Null), value);
+                }
+            }
+            (target, extra) => *target = extra,
+        }
+    }
+
+    fn test_engine(dir: &std::path::Path) -> WorkflowEngine {
+        WorkflowEngine::new(WorkflowConfig {
+            workflow_dir: dir.to_path_buf(),
+            ..WorkflowConfig::default()
+        })
+    }
+
+    fn command_step(id: &str, command: &str, args: &[&str], depends_on: &[&str]) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "name": id,
+            "step_type": "Command",
+            "command": command,
+            "args": args,
+            "timeout": 30,
+            "retry_count": 0,
+            "depends_on": depends_on,
+            "condition": null,
+            "output": null
+        })
+    }
+
+    fn only_execution_id(dir: &std::path::Path) -> uuid::Uuid {
+        let records: Vec<_> = std::fs::read_dir(dir.join("executions")).unwrap().collect();
+        assert_eq!(records.len(), 1);
+        let path = records[0].as_ref().unwrap().path();
+        path.file_stem().unwrap().to_string_lossy().parse().unwrap()
+    }
 
     #[test]
     fn test_config_default() {
@@ -9,12 +105,24 @@ mod tests {
         assert_eq!(config.workflow.max_concurrent_workflows, 4);
     }
 
+    #[test]
+    fn test_config_load_validates_retry_policy() {
+        let config = Config::load("config.yaml").unwrap();
+        assert_eq!(config.workflow.retry_policy.max_delay_ms, Some(30000));
+
+        let dir = temp_workflow_dir();
+        let path = dir.join("config.yaml");
+        let invalid = std::fs::read_to_string("config.yaml").unwrap().replace("jitter: 0.1", "jitter: 1.5");
+        std::fs::write(&path, invalid).unwrap();
+        let error = Config::load(path.to_str().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("jitter must be between 0.0 and 1.0"), "{}", error);
+    }
+
     #[test]
     fn test_upload_manager_creation() {
         let config = Config::default();
         let _upload_manager = UploadManager::new(config.upload);
         // Test that creation doesn't panic
-        assert!(true);
     }
 
     #[test]
@@ -22,6 +130,1106 @@ mod tests {
         let config = Config::default();
         let _workflow_engine = WorkflowEngine::new(config.workflow);
         // Test that creation doesn't panic
-        assert!(true);
+    }
+
+    #[tokio::test]
+    async fn test_independent_steps_run_in_parallel() {
+        let dir = temp_workflow_dir();
+        let workflow_path = write_workflow(&dir, serde_json::json!([
+            command_step("a", "sleep", &["1"], &[]),
+            command_step("b", "sleep", &["1"], &[]),
+            command_step("c", "true", &[], &["a", "b"]),
+        ]), serde_json::json!({}));
+
+        let execution = test_engine(&dir).execute_workflow(&workflow_path).await.unwrap();
+
+        assert_eq!(execution.steps_executed.len(), 3);
+        let step = |id: &str| execution.steps_executed.iter().find(|step| step.step_id == id).unwrap();
+        let (a, b, c) = (step("a"), step("b"), step("c"));
+        // `a` and `b` overlap, and `c` only starts once both finished
+        assert!(a.started_at < b.completed_at.unwrap() && b.started_at < a.completed_at.unwrap());
+        assert!(c.started_at >= a.completed_at.unwrap() && c.started_at >= b.completed_at.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_step_outputs_are_published_as_variables() {
+        let dir = temp_workflow_dir();
+        let mut producer = command_step("produce", "echo", &["hello"], &[]);
+        producer["output"] = serde_json::json!("greeting");
+        let workflow_path = write_workflow(&dir, serde_json::json!([
+            producer,
+            command_step("consume", "sh", &["-c", "test \"$greeting\" = hello"], &["produce"]),
+            command_step("report", "echo", &["$greeting.status/$greeting.exit_code.txt"], &["produce"]),
+        ]), serde_json::json!({}));
+
+        let engine = test_engine(&dir);
+        assert!(engine.validate_workflow(&workflow_path).await.unwrap().is_empty());
+        let execution = engine.execute_workflow(&workflow_path).await.unwrap();
+
+        assert_eq!(execution.variables["greeting"], "hello");
+        assert_eq!(execution.variables["steps.produce.output"], "hello");
+        assert_eq!(execution.variables["steps.produce.exit_code"], "0");
+        assert_eq!(execution.variables["steps.consume.status"], "Completed");
+        assert_eq!(execution.variables["steps.report.output"], "Completed/0.txt");
+    }
+
+    #[test]
+    fn test_interpolation() {
+        use crate::workflow::interpolation::interpolate;
+
+        let variables: std::collections::HashMap<String, String> = [
+            ("input_file".to_string(), "report.txt".to_string()),
+            ("steps.scan.output".to_string(), "clean".to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            interpolate("Processing completed for $input_file.", &variables).unwrap(),
+            "Processing completed for report.txt."
+        );
+        assert_eq!(interpolate("$input_file.gz", &variables).unwrap(), "report.txt.gz");
+        assert_eq!(interpolate("$steps.scan.output", &variables).unwrap(), "clean");
+        assert_eq!(interpolate("${steps.scan.output}/$$HOME", &variables).unwrap(), "clean/$HOME");
+        assert_eq!(interpolate("${missing:-fallback}", &variables).unwrap(), "fallback");
+        assert!(interpolate("$missing", &variables).is_err());
+    }
+
+    #[test]
+    fn test_condition_expressions() {
+        use crate::workflow::Expr;
+
+        let variables: std::collections::HashMap<String, String> = [
+            ("validation_result".to_string(), "success".to_string()),
+            ("file_count".to_string(), "12".to_string()),
+            ("steps.scan.status".to_string(), "Completed".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let check = |source: &str| Expr::parse(source).unwrap().is_true(&variables).unwrap();
+
+        assert!(check("$validation_result == 'success'"));
+        assert!(check("$file_count > 9 && steps.scan.status == \"Completed\""));
+        assert!(check("!exists($missing) || contains($missing, 'x')"));
+        assert!(check("matches(${validation_result}, '^succ') && !($file_count < 10)"));
+        assert!(!check("$missing == 'success'"));
+
+        assert!(Expr::parse("$a == ").is_err());
+        assert!(Expr::parse("unknown($a)").is_err());
+        assert!(Expr::parse("($a == 'b'").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_builtin_step_types() {
+        let dir = temp_workflow_dir();
+        let input = dir.join("input.txt");
+        std::fs::write(&input, "hello hello hello hello").unwrap();
+        let input = input.to_string_lossy().to_string();
+
+        let mut validate = command_step("validate", "exists,readable,extension=txt", &[&input], &[]);
+        validate["step_type"] = serde_json::json!("Validate");
+        let mut compress = command_step("compress", "compress", &[&input], &["validate"]);
+        compress["step_type"] = serde_json::json!("Transform");
+        compress["condition"] = serde_json::json!("steps.validate.output == 'success'");
+        let mut script = command_step("script", "test -f \"$1\"", &["${steps.compress.path}"], &["compress"]);
+        script["step_type"] = serde_json::json!("Script");
+        let workflow_path =
+            write_workflow(&dir, serde_json::json!([validate, compress, script]), serde_json::json!({}));
+
+        let execution = test_engine(&dir).execute_workflow(&workflow_path).await.unwrap();
+
+        assert_eq!(execution.variables["steps.compress.path"], format!("{}.gz", input));
+        assert!(execution.variables.contains_key("steps.compress.compression_ratio"));
+        assert_eq!(execution.variables["steps.script.status"], "Completed");
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_step_is_terminated() {
+        let dir = temp_workflow_dir();
+        let mut step = command_step("slow", "sh", &["-c", "sleep 30"], &[]);
+        step["timeout"] = serde_json::json!(1);
+        let workflow_path = write_workflow(&dir, serde_json::json!([step]), serde_json::json!({}));
+
+        let started = std::time::Instant::now();
+        let result = test_engine(&dir).execute_workflow(&workflow_path).await;
+
+        assert!(result.unwrap_err().to_string().contains("timed out after 1s"));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_failed_execution_is_persisted() {
+        let dir = temp_workflow_dir();
+        let workflow_path = write_workflow(&dir, serde_json::json!([
+            command_step("ok", "true", &[], &[]),
+            command_step("broken", "false", &[], &["ok"]),
+        ]), serde_json::json!({}));
+
+        let engine = test_engine(&dir);
+        assert!(engine.execute_workflow(&workflow_path).await.is_err());
+
+        let execution_id = only_execution_id(&dir);
+        let execution = engine.get_execution(execution_id).await.unwrap().unwrap();
+
+        assert!(matches!(execution.status, ExecutionStatus::Failed));
+        assert!(execution.completed_at.is_some());
+        assert!(matches!(execution.steps_executed[0].status, ExecutionStatus::Completed));
+        assert!(matches!(execution.steps_executed[1].status, ExecutionStatus::Failed));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_execution_is_finalized() {
+        let dir = temp_workflow_dir();
+        let workflow_path = write_workflow(&dir, serde_json::json!([
+            command_step("slow", "sleep", &["30"], &[]),
+        ]), serde_json::json!({}));
+
+        let engine = std::sync::Arc::new(test_engine(&dir));
+        let runner = std::sync::Arc::clone(&engine);
+        let handle = tokio::spawn(async move { runner.execute_workflow(&workflow_path).await });
+
+        while engine.active_executions().is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        let execution_id = engine.active_executions()[0];
+        assert!(engine.cancel_execution(execution_id));
+        assert!(handle.await.unwrap().is_err());
+
+        let execution = engine.get_execution(execution_id).await.unwrap().unwrap();
+        assert!(matches!(execution.status, ExecutionStatus::Cancelled));
+        assert!(matches!(execution.steps_executed[0].status, ExecutionStatus::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn test_resume_skips_completed_steps() {
+        let dir = temp_workflow_dir();
+        let counter = dir.join("counter.txt").to_string_lossy().to_string();
+        let marker = dir.join("ready").to_string_lossy().to_string();
+        let mut each = command_step("each", "echo", &["$item"], &["count"]);
+        each["for_each"] = serde_json::json!({ "items": ["a", "b"] });
+        let workflow_path = write_workflow(&dir, serde_json::json!([
+            command_step("count", "sh", &["-c", &format!("echo run >> {}", counter)], &[]),
+            each,
+            command_step("gate", "test", &["-f", &marker], &["each"]),
+            command_step("use", "echo", &["${steps.each-1.output}"], &["gate"]),
+        ]), serde_json::json!({}));
+
+        let engine = test_engine(&dir);
+        assert!(engine.execute_workflow(&workflow_path).await.is_err());
+        let execution_id = only_execution_id(&dir);
+
+        // Results are restored from the kept step records, not the saved variables
+        let record_path = dir.join("executions").join(format!("{}.json", execution_id));
+        let mut record: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&record_path).unwrap()).unwrap();
+        record["variables"].as_object_mut().unwrap().retain(|name, _| !name.starts_with("steps.each"));
+        std::fs::write(&record_path, record.to_string()).unwrap();
+
+        std::fs::write(&marker, "").unwrap();
+        let execution = engine.resume(execution_id).await.unwrap();
+
+        assert!(matches!(execution.status, ExecutionStatus::Completed));
+        assert_eq!(execution.resume_count, 1);
+        assert_eq!(std::fs::read_to_string(&counter).unwrap().lines().count(), 1);
+        assert_eq!(execution.variables["steps.use.output"], "b");
+        assert!(engine.resume(execution_id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resume_refuses_changed_workflow() {
+        let dir = temp_workflow_dir();
+        let workflow_path = write_workflow(&dir, serde_json::json!([
+            command_step("broken", "false", &[], &[]),
+        ]), serde_json::json!({}));
+
+        let engine = test_engine(&dir);
+        assert!(engine.execute_workflow(&workflow_path).await.is_err());
+        let execution_id = only_execution_id(&dir);
+
+        let mut workflow: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&workflow_path).unwrap()).unwrap();
+        workflow["steps"][0]["command"] = serde_json::json!("true");
+        std::fs::write(&workflow_path, workflow.to_string()).unwrap();
+
+        let error = engine.resume(execution_id).await.unwrap_err();
+        assert!(error.to_string().contains("changed since execution"));
+    }
+
+    #[tokio::test]
+    async fn test_shell_mode_and_process_environment() {
+        let dir = temp_workflow_dir();
+        std::fs::create_dir_all(dir.join("work")).unwrap();
+
+        let mut redirect = command_step("redirect", "echo", &["$message", ">", "out.txt"], &[]);
+        redirect["shell"] = serde_json::json!(true);
+        redirect["working_dir"] = serde_json::json!(dir.join("work"));
+        let mut environment = command_step("environment", "sh", &["-c", "cat; echo \" $$GREETING $$HOME\""], &[]);
+        environment["env"] = serde_json::json!({ "GREETING": "hi $message" });
+        environment["stdin"] = serde_json::json!("from stdin");
+        environment["inherit_env"] = serde_json::json!(false);
+        environment["output"] = serde_json::json!("environment");
+        let workflow_path = write_workflow(
+            &dir,
+            serde_json::json!([redirect, environment]),
+            serde_json::json!({ "variables": { "message": "it's $(not) run" } }),
+        );
+
+        let execution = test_engine(&dir).execute_workflow(&workflow_path).await.unwrap();
+
+        let written = std::fs::read_to_string(dir.join("work").join("out.txt")).unwrap();
+        assert_eq!(written.trim_end(), "it's $(not) run");
+        assert_eq!(execution.variables["environment"], "from stdin hi it's $(not) run");
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy_filters_and_records_attempts() {
+        let dir = temp_workflow_dir();
+        let counter = dir.join("attempts.txt").to_string_lossy().to_string();
+        // Exits 75 (retryable) on the first run and 1 (not retryable) afterwards
+        let script = format!("echo run >> {0}; [ $(wc -l < {0}) -gt 1 ] && exit 1; exit 75", counter);
+        let mut flaky = command_step("flaky", "sh", &["-c", &script], &[]);
+        flaky["retry_count"] = serde_json::json!(5);
+        flaky["retry_policy"] = serde_json::json!({
+            "backoff": "Fixed",
+            "delay_ms": 10,
+            "retry_on_exit_codes": [75]
+        });
+        let workflow_path = write_workflow(&dir, serde_json::json!([flaky]), serde_json::json!({}));
+
+        let engine = test_engine(&dir);
+        assert!(engine.execute_workflow(&workflow_path).await.is_err());
+
+        let execution = engine.get_execution(only_execution_id(&dir)).await.unwrap().unwrap();
+        let attempts = &execution.steps_executed[0].attempts;
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(attempts[0].exit_code, Some(75));
+        assert_eq!(attempts[0].retry_delay_ms, Some(10));
+        assert_eq!(attempts[1].exit_code, Some(1));
+        assert_eq!(attempts[1].retry_delay_ms, None);
+
+        let exponential = RetryPolicy { max_delay_ms: Some(3000), ..RetryPolicy::default() };
+        let delays: Vec<u128> = (1..=4).map(|retry| exponential.delay(retry).as_millis()).collect();
+        assert_eq!(delays, vec![1000, 2000, 3000, 3000]);
+
+        // Stderr patterns compile when the policy is read, not per attempt
+        let policy: RetryPolicy = serde_json::from_value(serde_json::json!({ "retry_on_stderr": ["timed? out"] })).unwrap();
+        assert!(policy.is_retryable(Some(1), "connection timed out"));
+        assert!(!policy.is_retryable(Some(1), "permission denied"));
+        assert_eq!(serde_json::to_value(&policy).unwrap()["retry_on_stderr"], serde_json::json!(["timed? out"]));
+        let invalid = serde_json::from_value::<RetryPolicy>(serde_json::json!({ "retry_on_stderr": ["(unclosed"] }));
+        assert!(invalid.unwrap_err().to_string().contains("Invalid retry_on_stderr pattern '(unclosed'"));
+    }
+
+    #[tokio::test]
+    async fn test_overrunning_the_estimated_duration_is_recorded() {
+        let dir = temp_workflow_dir();
+        let workflow_path = write_workflow(
+            &dir,
+            serde_json::json!([command_step("slow", "sleep", &["1.1"], &[])]),
+            serde_json::json!({}),
+        );
+        let engine = test_engine(&dir);
+
+        let within = engine.execute_workflow(&workflow_path).await.unwrap();
+        assert!(within.estimate_exceeded_at.is_none());
+
+        let workflow_path = write_workflow(
+            &dir,
+            serde_json::json!([command_step("slow", "sleep", &["1.1"], &[])]),
+            serde_json::json!({ "metadata": { "estimated_duration": 1 } }),
+        );
+        let over = engine.execute_workflow(&workflow_path).await.unwrap();
+        let exceeded_at = over.estimate_exceeded_at.unwrap();
+        assert!(exceeded_at > over.started_at && exceeded_at < over.completed_at.unwrap());
+        let record = engine.get_execution(over.id).await.unwrap().unwrap();
+        assert_eq!(record.estimate_exceeded_at, Some(exceeded_at));
+    }
+
+    #[tokio::test]
+    async fn test_failure_handling_policies() {
+        let dir = temp_workflow_dir();
+        let mut flaky = command_step("flaky", "false", &[], &[]);
+        flaky["continue_on_error"] = serde_json::json!(true);
+        let workflow_path = write_workflow(&dir, serde_json::json!([
+            flaky,
+            command_step("after", "true", &[], &["flaky"]),
+        ]), serde_json::json!({}));
+
+        let execution = test_engine(&dir).execute_workflow(&workflow_path).await.unwrap();
+        assert!(matches!(execution.status, ExecutionStatus::CompletedWithErrors));
+        assert!(matches!(execution.steps_executed[1].status, ExecutionStatus::Completed));
+
+        let dir = temp_workflow_dir();
+        let mut on_failure = command_step("report", "true", &[], &["build"]);
+        on_failure["run_if"] = serde_json::json!("OnFailure");
+        let mut always = command_step("summary", "true", &[], &["deploy"]);
+        always["run_if"] = serde_json::json!("Always");
+        let workflow_path = write_workflow(&dir, serde_json::json!([
+            command_step("build", "false", &[], &[]),
+            command_step("deploy", "true", &[], &["build"]),
+            on_failure,
+            always,
+        ]), serde_json::json!({ "finally": [command_step("cleanup", "true", &[], &[])] }));
+
+        let engine = test_engine(&dir);
+        assert!(engine.execute_workflow(&workflow_path).await.is_err());
+        let execution = engine.get_execution(only_execution_id(&dir)).await.unwrap().unwrap();
+
+        let status = |id: &str| {
+            let step = execution.steps_executed.iter().find(|step| step.step_id == id).unwrap();
+            step.status.to_string()
+        };
+        assert!(matches!(execution.status, ExecutionStatus::Failed));
+        assert_eq!(status("deploy"), "Skipped");
+        assert_eq!(status("report"), "Completed");
+        assert_eq!(status("summary"), "Completed");
+        assert_eq!(status("cleanup"), "Completed");
+    }
+
+    #[tokio::test]
+    async fn test_workflow_deadline_cancels_remaining_steps() {
+        let dir = temp_workflow_dir();
+        let workflow_path = write_workflow(&dir, serde_json::json!([
+            command_step("slow", "sleep", &["30"], &[]),
+            command_step("after", "true", &[], &["slow"]),
+        ]), serde_json::json!({ "timeout_seconds": 1 }));
+
+        let engine = test_engine(&dir);
+        let started = std::time::Instant::now();
+        assert!(engine.execute_workflow(&workflow_path).await.is_err());
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        let execution = engine.get_execution(only_execution_id(&dir)).await.unwrap().unwrap();
+        assert!(matches!(execution.status, ExecutionStatus::TimedOut));
+        assert!(execution.deadline.is_some());
+        assert!(execution.steps_executed[0].termination_reason.is_some());
+        assert!(execution
+            .steps_executed
+            .iter()
+            .all(|step| !matches!(step.status, ExecutionStatus::Completed)));
+    }
+
+    #[tokio::test]
+    async fn test_finally_runs_after_deadline() {
+        let dir = temp_workflow_dir();
+        let cleaned = dir.join("cleaned").display().to_string();
+        let workflow_path = write_workflow(
+            &dir,
+            serde_json::json!([command_step("slow", "sleep", &["5"], &[])]),
+            serde_json::json!({
+                "timeout_seconds": 1,
+                "finally": [command_step("cleanup", "touch", &[&cleaned], &[])]
+            }),
+        );
+
+        let engine = test_engine(&dir);
+        assert!(engine.execute_workflow(&workflow_path).await.is_err());
+        let execution = engine.get_execution(only_execution_id(&dir)).await.unwrap().unwrap();
+        assert!(matches!(execution.status, ExecutionStatus::TimedOut));
+        let cleanup = execution.steps_executed.iter().find(|step| step.step_id == "cleanup").unwrap();
+        assert!(matches!(cleanup.status, ExecutionStatus::Completed), "{:?}", cleanup.error_message);
+        assert!(std::path::Path::new(&cleaned).exists());
+    }
+
+    #[tokio::test]
+    async fn test_for_each_and_matrix_expansion() {
+        let dir = temp_workflow_dir();
+        std::fs::create_dir_all(dir.join("in")).unwrap();
+        for (name, content) in [("a.txt", "A"), ("b.txt", "B"), ("c.txt", "C")] {
+            std::fs::write(dir.join("in").join(name), content).unwrap();
+        }
+
+        let mut each = command_step("each", "cat", &["$file"], &[]);
+        each["for_each"] = serde_json::json!({ "as": "file", "glob": format!("{}/in/*.txt", dir.display()) });
+        each["max_parallel"] = serde_json::json!(1);
+        each["output"] = serde_json::json!("contents");
+        let mut combo = command_step("combo", "echo", &["$os-$arch"], &["each"]);
+        combo["matrix"] = serde_json::json!({ "os": ["linux", "mac"], "arch": ["x86"] });
+        let mut lines = command_step("lines", "echo", &["got $item"], &["combo"]);
+        lines["for_each"] = serde_json::json!({ "lines": "combo" });
+        let workflow_path = write_workflow(&dir, serde_json::json!([each, combo, lines]), serde_json::json!({}));
+
+        let execution = test_engine(&dir).execute_workflow(&workflow_path).await.unwrap();
+
+        assert_eq!(execution.variables["contents"], "A\nB\nC");
+        assert_eq!(execution.variables["steps.combo.output"], "linux-x86\nmac-x86");
+        assert_eq!(execution.variables["steps.lines.output"], "got linux-x86\ngot mac-x86");
+        assert_eq!(execution.variables["steps.each-1.output"], "B");
+        let instances = execution
+            .steps_executed
+            .iter()
+            .filter(|step| step.instance_of.as_deref() == Some("each"))
+            .count();
+        assert_eq!(instances, 3);
+
+        // A declared id in the `<id>-<n>` form of an expanded step's instances is rejected
+        let mut build = command_step("build", "echo", &["$item"], &[]);
+        build["for_each"] = serde_json::json!({ "items": ["a", "b"] });
+        let workflow_path = write_workflow(
+            &dir,
+            serde_json::json!([build, command_step("build-1", "true", &[], &[])]),
+            serde_json::json!({}),
+        );
+        let engine = test_engine(&dir);
+        let error = engine.execute_workflow(&workflow_path).await.unwrap_err();
+        let expected = "step id 'build-1' is reserved for the instances of expanded step 'build'";
+        assert!(error.to_string().contains(expected), "{}", error);
+        let issues = engine.validate_workflow(&workflow_path).await.unwrap();
+        assert!(issues.iter().any(|issue| issue.location == "steps[1].id" && issue.message == expected));
+    }
+
+    #[tokio::test]
+    async fn test_sub_workflow_step() {
+        let dir = temp_workflow_dir();
+        let mut greet = command_step("greet", "echo", &["hello $name"], &[]);
+        greet["output"] = serde_json::json!("message");
+        let child_path = write_workflow(&dir, serde_json::json!([greet]), serde_json::json!({}));
+        std::fs::rename(&child_path, dir.join("child.json")).unwrap();
+
+        let mut call = command_step("call", "child.json", &[], &[]);
+        call["step_type"] = serde_json::json!("Workflow");
+        call["inputs"] = serde_json::json!({ "name": "$who" });
+        call["outputs"] = serde_json::json!({ "greeting": "message" });
+        let workflow_path = write_workflow(&dir, serde_json::json!([
+            call,
+            command_step("show", "echo", &["${steps.call.greeting}"], &["call"]),
+        ]), serde_json::json!({ "variables": { "who": "world" } }));
+
+        let engine = test_engine(&dir);
+        let execution = engine.execute_workflow(&workflow_path).await.unwrap();
+        assert_eq!(execution.variables["steps.show.output"], "hello world");
+
+        let child_id = execution.steps_executed[0].child_execution_id.unwrap();
+        let child = engine.get_execution(child_id).await.unwrap().unwrap();
+        assert_eq!(child.parent_execution_id, Some(execution.id));
+
+        // A workflow that calls itself is rejected before anything runs
+        let mut recurse = command_step("recurse", "workflow.json", &[], &[]);
+        recurse["step_type"] = serde_json::json!("Workflow");
+        let workflow_path = write_workflow(&dir, serde_json::json!([recurse]), serde_json::json!({}));
+        let error = engine.execute_workflow(&workflow_path).await.unwrap_err();
+        assert!(error.to_string().contains("Circular sub-workflow reference"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_sub_workflow_is_not_retried() {
+        let dir = temp_workflow_dir();
+        let child_path =
+            write_workflow(&dir, serde_json::json!([command_step("fail", "false", &[], &[])]), serde_json::json!({}));
+        std::fs::rename(&child_path, dir.join("child.json")).unwrap();
+        let mut call = command_step("call", "child.json", &[], &[]);
+        call["step_type"] = serde_json::json!("Workflow");
+        call.as_object_mut().unwrap().remove("retry_count");
+        let workflow_path = write_workflow(&dir, serde_json::json!([call]), serde_json::json!({}));
+
+        // Each retry would rerun the whole child as a new execution
+        assert!(WorkflowConfig::default().retry_attempts > 0);
+        let engine = test_engine(&dir);
+        assert!(engine.execute_workflow(&workflow_path).await.is_err());
+        let records = std::fs::read_dir(dir.join("executions")).unwrap().count();
+        assert_eq!(records, 2);
+    }
+
+    #[tokio::test]
+    async fn test_sub_workflow_with_one_step_slot() {
+        let dir = temp_workflow_dir();
+        let child_path =
+            write_workflow(&dir, serde_json::json!([command_step("hi", "echo", &["hi"], &[])]), serde_json::json!({}));
+        std::fs::rename(&child_path, dir.join("child.json")).unwrap();
+        let mut call = command_step("call", "child.json", &[], &[]);
+        call["step_type"] = serde_json::json!("Workflow");
+        call["timeout"] = serde_json::json!(5);
+        let workflow_path = write_workflow(&dir, serde_json::json!([call]), serde_json::json!({}));
+
+        // The child's step needs the only slot, so the parent step must not hold it
+        let engine = WorkflowEngine::new(WorkflowConfig {
+            workflow_dir: dir.clone(),
+            max_parallel_steps: 1,
+            ..WorkflowConfig::default()
+        });
+        let execution = engine.execute_workflow(&workflow_path).await.unwrap();
+        assert!(matches!(execution.steps_executed[0].status, ExecutionStatus::Completed));
+    }
+
+    #[tokio::test]
+    async fn test_typed_workflow_inputs() {
+        let dir = temp_workflow_dir();
+        let mut show = command_step("show", "echo", &["$count $verbose"], &[]);
+        show["output"] = serde_json::json!("shown");
+        let workflow_path = write_workflow(
+            &dir,
+            serde_json::json!([show]),
+            serde_json::json!({
+                "inputs": {
+                    "count": { "type": "Integer", "required": true, "pattern": "[0-9]+" },
+                    "verbose": { "type": "Boolean", "default": false },
+                    "source": { "type": "File", "required": true }
+                }
+            }),
+        );
+
+        let engine = test_engine(&dir);
+        let error = engine
+            .execute_workflow_with_inputs(&workflow_path, &[("count".to_string(), "-1".to_string())].into())
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("count: '-1' does not match pattern"));
+        assert!(error.contains("source is required"));
+        // Rejected before an execution record was even created
+        assert!(!dir.join("executions").exists());
+
+        let inputs = [
+            ("count".to_string(), "3".to_string()),
+            ("source".to_string(), workflow_path.clone()),
+            ("verbose".to_string(), "yes".to_string()),
+        ]
+        .into();
+        let execution = engine.execute_workflow_with_inputs(&workflow_path, &inputs).await.unwrap();
+        assert_eq!(execution.variables["shown"], "3 true");
+    }
+
+    #[tokio::test]
+    async fn test_secrets_are_injected_and_redacted() {
+        let dir = temp_workflow_dir();
+        std::fs::write(dir.join("token.txt"), "hunter2-secret\n").unwrap();
+
+        let mut show = command_step("show", "sh", &["-c", "echo token=$token"], &[]);
+        show["output"] = serde_json::json!("shown");
+        let mut leak = command_step("leak", "sh", &["-c", "echo \"$token\" >&2; exit 1"], &[]);
+        leak["continue_on_error"] = serde_json::json!(true);
+        let workflow_path = write_workflow(
+            &dir,
+            serde_json::json!([show, leak]),
+            serde_json::json!({ "secrets": { "token": { "file": dir.join("token.txt") } } }),
+        );
+
+        let execution = test_engine(&dir).execute_workflow(&workflow_path).await.unwrap();
+        assert_eq!(execution.variables["shown"], "token=***");
+        let leak = execution.steps_executed.iter().find(|step| step.step_id == "leak").unwrap();
+        let error = leak.error_message.as_deref().unwrap();
+        assert!(error.contains("***") && !error.contains("hunter2"));
+
+        let record = std::fs::read_to_string(dir.join("executions").join(format!("{}.json", execution.id))).unwrap();
+        assert!(record.contains("token=***"));
+        assert!(!record.contains("hunter2"));
+
+        let sealed = secrets::seal(&[("token".to_string(), "hunter2".to_string())].into(), "passphrase").unwrap();
+        assert_eq!(secrets::unseal(&sealed, "passphrase").unwrap()["token"], "hunter2");
+        assert!(secrets::unseal(&sealed, "wrong").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parent_secrets_are_only_redacted_in_sub_workflows() {
+        let dir = temp_workflow_dir();
+        std::fs::write(dir.join("token.txt"), "hunter2-secret\n").unwrap();
+        let mut show = command_step("show", "sh", &["-c", "printenv token; echo $from_parent"], &[]);
+        show["output"] = serde_json::json!("shown");
+        let child_path = write_workflow(
+            &dir,
+            serde_json::json!([show]),
+            serde_json::json!({ "variables": { "token": "child-value" }, "inputs": { "from_parent": {} } }),
+        );
+        std::fs::rename(&child_path, dir.join("child.json")).unwrap();
+
+        let mut call = command_step("call", "child.json", &[], &[]);
+        call["step_type"] = serde_json::json!("Workflow");
+        call["inputs"] = serde_json::json!({ "from_parent": "$token" });
+        call["outputs"] = serde_json::json!({ "shown": "shown" });
+        let workflow_path = write_workflow(
+            &dir,
+            serde_json::json!([call]),
+            serde_json::json!({ "secrets": { "token": { "file": dir.join("token.txt") } } }),
+        );
+
+        // The child keeps its own `token` and redacts the parent's value it received
+        let execution = test_engine(&dir).execute_workflow(&workflow_path).await.unwrap();
+        assert_eq!(execution.variables["steps.call.shown"], "child-value\n***");
+    }
+
+    #[tokio::test]
+    async fn test_step_artifacts_are_collected() {
+        let dir = temp_workflow_dir();
+        let work_dir = dir.join("work");
+        std::fs::create_dir_all(&work_dir).unwrap();
+
+        let mut produce = command_step("produce", "sh", &["-c", "echo one > a.txt; echo two > b.txt"], &[]);
+        produce["working_dir"] = serde_json::json!(work_dir);
+        produce["artifacts"] = serde_json::json!(["*.txt"]);
+        let consume = command_step("consume", "cat", &["$steps.produce.artifacts.b.txt"], &["produce"]);
+        let mut missing = command_step("missing", "true", &[], &[]);
+        missing["artifacts"] = serde_json::json!(["does-not-exist.bin"]);
+        missing["continue_on_error"] = serde_json::json!(true);
+        missing["retry_count"] = serde_json::json!(1);
+        missing["retry_policy"] = serde_json::json!({ "delay_ms": 10 });
+        let workflow_path = write_workflow(&dir, serde_json::json!([produce, consume, missing]), serde_json::json!({}));
+
+        let engine = test_engine(&dir);
+        let execution = engine.execute_workflow(&workflow_path).await.unwrap();
+        assert!(matches!(execution.status, ExecutionStatus::CompletedWithErrors));
+        assert_eq!(execution.variables["steps.consume.output"], "two");
+        let missing = execution.steps_executed.iter().find(|step| step.step_id == "missing").unwrap();
+        assert!(missing.error_message.as_deref().unwrap().contains("matched no files"));
+        // A failed collection is a failed attempt, retried like any other
+        assert_eq!(missing.attempts.len(), 2);
+        assert!(missing.attempts.iter().all(|attempt| attempt.error_message.as_deref().unwrap().contains("matched no files")));
+
+        let artifacts = engine.list_artifacts(execution.id).await.unwrap();
+        let names: Vec<&str> = artifacts.iter().map(|artifact| artifact.name.as_str()).collect();
+        assert_eq!(names, ["a.txt", "b.txt"]);
+        assert_eq!(artifacts[0].size, 4);
+        assert!(artifacts[0].path.starts_with(engine.artifacts_dir(execution.id)));
+
+        let path = engine.get_artifact(execution.id, "produce", "a.txt").await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\n");
+        std::fs::write(&path, "tampered").unwrap();
+        assert!(engine.get_artifact(execution.id, "produce", "a.txt").await.is_err());
+
+        assert_eq!(engine.cleanup_artifacts(execution.started_at).await.unwrap(), 0);
+        assert_eq!(engine.cleanup_artifacts(chrono::Utc::now()).await.unwrap(), 1);
+        assert!(!engine.artifacts_dir(execution.id).exists());
+        assert!(engine.get_execution(execution.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_step_results_are_cached() {
+        let dir = temp_workflow_dir();
+        let work_dir = dir.join("work");
+        std::fs::create_dir_all(&work_dir).unwrap();
+        std::fs::write(work_dir.join("input.txt"), "first").unwrap();
+
+        let mut build = command_step("build", "sh", &["-c", "echo run >> runs.log; cp input.txt out.txt; echo built"], &[]);
+        build["working_dir"] = serde_json::json!(work_dir);
+        build["artifacts"] = serde_json::json!(["out.txt"]);
+        build["cache"] = serde_json::json!({ "files": ["input.txt"], "env": ["MODE"] });
+        let mut other = command_step("other", "echo", &["12345678"], &[]);
+        other["cache"] = serde_json::json!({});
+        let fast = serde_json::json!({ "variables": { "MODE": "fast" } });
+        let other_path = dir.join("other.json");
+        std::fs::rename(write_workflow(&dir, serde_json::json!([other]), fast.clone()), &other_path).unwrap();
+        let workflow_path = write_workflow(&dir, serde_json::json!([build]), fast);
+
+        let engine = test_engine(&dir).with_step_cache(StepCache::new(dir.join("cache"), 24));
+        let runs = || std::fs::read_to_string(work_dir.join("runs.log")).unwrap().lines().count();
+
+        let first = engine.execute_workflow(&workflow_path).await.unwrap();
+        assert!(matches!(first.steps_executed[0].status, ExecutionStatus::Completed));
+        std::fs::remove_file(work_dir.join("out.txt")).unwrap();
+        let second = engine.execute_workflow(&workflow_path).await.unwrap();
+        assert!(matches!(second.status, ExecutionStatus::Completed));
+        assert!(matches!(second.steps_executed[0].status, ExecutionStatus::Cached));
+        assert_eq!(second.variables["steps.build.output"], "built");
+        assert_eq!(runs(), 1);
+        let restored = engine.get_artifact(second.id, "build", "out.txt").await.unwrap();
+        assert_eq!(std::fs::read_to_string(restored).unwrap(), "first");
+        // A hit also puts the artifact back where the step wrote it
+        assert_eq!(std::fs::read_to_string(work_dir.join("out.txt")).unwrap(), "first");
+
+        std::fs::write(work_dir.join("input.txt"), "second").unwrap();
+        let third = engine.execute_workflow(&workflow_path).await.unwrap();
+        assert!(matches!(third.steps_executed[0].status, ExecutionStatus::Completed));
+        assert_eq!(runs(), 2);
+
+        // The two "build" entries take 11 and 12 bytes; the 9 bytes of "other"
+        // exceed the 24 byte limit, so the least recently used entry goes
+        let cache = engine.step_cache().unwrap();
+        let evicted = first.steps_executed[0].cache_key.clone().unwrap();
+        assert!(cache.contains(&evicted));
+        engine.execute_workflow(other_path.to_str().unwrap()).await.unwrap();
+        assert!(!cache.contains(&evicted));
+        assert!(cache.size().unwrap() <= 24);
+
+        // Workflow variables named in `env` are part of the key
+        engine.execute_workflow(&workflow_path).await.unwrap();
+        assert_eq!(runs(), 2);
+        write_workflow(&dir, serde_json::json!([build]), serde_json::json!({ "variables": { "MODE": "slow" } }));
+        engine.execute_workflow(&workflow_path).await.unwrap();
+        assert_eq!(runs(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_plan_previews_without_running() {
+        let dir = temp_workflow_dir();
+        let mut first = command_step("first", "touch", &["$target"], &[]);
+        first["output"] = serde_json::json!("made");
+        let mut second = command_step("second", "echo", &["$made", "$token"], &["first"]);
+        second["condition"] = serde_json::json!("$made != ''");
+        let mut never = command_step("never", "echo", &["no"], &[]);
+        never["condition"] = serde_json::json!("$mode == 'full'");
+        let mut each = command_step("each", "echo", &["$item"], &["first"]);
+        each["for_each"] = serde_json::json!({ "items": ["a", "b c"] });
+        let workflow_path = write_workflow(
+            &dir,
+            serde_json::json!([first, second, never, each]),
+            serde_json::json!({
+                "variables": { "target": "out file.txt", "mode": "quick" },
+                "secrets": { "token": { "env": "PLAN_TEST_UNSET_SECRET" } }
+            }),
+        );
+
+        let plan = test_engine(&dir).plan(&workflow_path, &std::collections::HashMap::new()).await.unwrap();
+        let stage_ids: Vec<Vec<&str>> = plan
+            .stages
+            .iter()
+            .map(|stage| stage.iter().map(|step| step.id.as_str()).collect())
+            .collect();
+        assert_eq!(stage_ids, [vec!["first", "never"], vec!["second", "each"]]);
+
+        let step = |id: &str| plan.stages.iter().flatten().find(|step| step.id == id).unwrap();
+        assert_eq!(step("first").command, "touch 'out file.txt'");
+        assert_eq!(step("second").decision, PlanDecision::Conditional);
+        assert_eq!(step("second").command, "echo '${made}' '***'");
+        assert_eq!(step("never").decision, PlanDecision::Skip);
+        let instances: Vec<&str> = step("each").instances.iter().map(|step| step.command.as_str()).collect();
+        assert_eq!(instances, ["echo a", "echo 'b c'"]);
+
+        assert!(plan.to_string().contains("[skip] never"));
+        assert!(!dir.join("out file.txt").exists());
+        assert!(!dir.join("executions").exists());
+    }
+
+    #[tokio::test]
+    async fn test_validate_reports_all_problems() {
+        let dir = temp_workflow_dir();
+        let mut first = command_step("first", "no-such-program-here", &["$undefined_value"], &["third"]);
+        first["condition"] = serde_json::json!("$x ==");
+        let second = command_step("second", "echo", &["${steps.first.output}"], &["ghost"]);
+        let third = command_step("third", "echo", &[], &["first"]);
+        let duplicate = command_step("second", "echo", &[], &[]);
+        let workflow_path =
+            write_workflow(&dir, serde_json::json!([first, second, third, duplicate]), serde_json::json!({}));
+
+        let engine = test_engine(&dir);
+        let issues = engine.validate_workflow(&workflow_path).await.unwrap();
+        let found: Vec<String> = issues
+            .iter()
+            .map(|issue| format!("{}: {}", issue.location, issue.message))
+            .collect();
+        for expected in [
+            "steps[3].id: duplicate step id 'second'",
+            "steps[1].depends_on[0]: unknown step 'ghost'",
+            "steps: dependency cycle: first -> third -> first",
+            "steps[0].args[0]: undefined variable 'undefined_value'",
+            "steps[0].command: executable 'no-such-program-here' not found on PATH",
+        ] {
+            assert!(found.iter().any(|issue| issue == expected), "missing {:?} in {:?}", expected, found);
+        }
+        assert!(found.iter().any(|issue| issue.starts_with("steps[0].condition: Invalid condition")));
+        assert_eq!(found.len(), 6);
+
+        let workflow_path =
+            write_workflow(&dir, serde_json::json!([{ "id": "only", "step_type": "Command" }]), serde_json::json!({}));
+        let locations: Vec<String> = engine
+            .validate_workflow(&workflow_path)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|issue| issue.location)
+            .collect();
+        assert_eq!(locations, ["steps[0].name", "steps[0].args", "steps[0].depends_on"]);
+    }
+
+    #[tokio::test]
+    async fn test_yaml_and_toml_workflows() {
+        let dir = temp_workflow_dir();
+        let mut greet = command_step("greet", "echo", &["hello $who"], &[]);
+        greet["output"] = serde_json::json!("greeting");
+        let json_path =
+            write_workflow(&dir, serde_json::json!([greet]), serde_json::json!({ "variables": { "who": "yaml" } }));
+
+        let yaml_path = dir.join("workflow.yaml");
+        let toml_path = dir.join("workflow.toml");
+        format::convert(std::path::Path::new(&json_path), &yaml_path).unwrap();
+        format::convert(&yaml_path, &toml_path).unwrap();
+        assert!(std::fs::read_to_string(&toml_path).unwrap().contains("[[steps]]"));
+
+        let hashes: Vec<String> = [std::path::PathBuf::from(&json_path), yaml_path.clone(), toml_path.clone()]
+            .iter()
+            .map(|path| format::read_workflow(path).unwrap().definition_hash().unwrap())
+            .collect();
+        assert!(hashes.iter().all(|hash| hash == &hashes[0]));
+
+        let engine = test_engine(&dir);
+        let execution = engine.execute_workflow(yaml_path.to_str().unwrap()).await.unwrap();
+        assert_eq!(execution.variables["greeting"], "hello yaml");
+        assert_eq!(engine.list_workflows().await.unwrap().len(), 3);
+
+        std::fs::write(&yaml_path, "id: 1\nsteps:\n  - id: [unclosed\n").unwrap();
+        let error = engine.execute_workflow(yaml_path.to_str().unwrap()).await.unwrap_err().to_string();
+        assert!(error.contains("Invalid YAML workflow") && error.contains("line 3 column 9"), "{}", error);
+        std::fs::write(&toml_path, "id = \"x\"\nsteps = [\n").unwrap();
+        let issues = engine.validate_workflow(toml_path.to_str().unwrap()).await.unwrap();
+        assert!(issues[0].message.starts_with("invalid TOML") && issues[0].message.contains("line 2"));
+    }
+
+    #[tokio::test]
+    async fn test_step_templates_and_includes() {
+        let dir = temp_workflow_dir();
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(
+            dir.join("lib/common.yaml"),
+            "templates:\n  echo:\n    step_type: Command\n    command: echo\n    args: []\n    timeout: 30\n    depends_on: []\n  \
+             loud:\n    uses: echo\n    args: [\"$greeting!\"]\nvariables:\n  greeting: hello\n  who: library\n\
+             steps:\n  - id: setup\n    name: Setup\n    uses: echo\n    args: [setup]\n",
+        )
+        .unwrap();
+        let mut steps = serde_json::json!([
+            { "id": "greet", "name": "Greet", "uses": "loud", "depends_on": ["setup"], "output": "greeting_output" },
+            { "id": "who", "name": "Who", "uses": "echo", "args": ["$who"], "timeout": 5, "depends_on": ["greet"] }
+        ]);
+        let extra = serde_json::json!({ "include": ["lib/common.yaml"], "variables": { "who": "workflow" } });
+        let path = write_workflow(&dir, steps.clone(), extra.clone());
+
+        let resolved = format::read_workflow(std::path::Path::new(&path)).unwrap();
+        let ids: Vec<&str> = resolved.steps.iter().map(|step| step.id.as_str()).collect();
+        assert_eq!(ids, ["setup", "greet", "who"]);
+        assert_eq!(resolved.steps[1].args, ["$greeting!"]);
+        assert_eq!(resolved.steps[1].depends_on, ["setup"]);
+        assert!(matches!(resolved.steps[2].timeout, Some(Templated::Value(5))));
+
+        let engine = test_engine(&dir);
+        let execution = engine.execute_workflow(&path).await.unwrap();
+        assert_eq!(execution.variables["greeting_output"], "hello!");
+        assert_eq!(execution.variables["steps.who.output"], "workflow");
+        assert!(engine.validate_workflow(&path).await.unwrap().is_empty());
+
+        steps[0]["uses"] = serde_json::json!("missing");
+        write_workflow(&dir, steps, extra);
+        let issues = engine.validate_workflow(&path).await.unwrap();
+        assert_eq!(issues[0].message, "steps[0].uses: unknown step template 'missing'");
+
+        std::fs::write(dir.join("lib/common.yaml"), "include: [\"../lib/common.yaml\"]\n").unwrap();
+        let error = engine.execute_workflow(&path).await.unwrap_err().to_string();
+        assert!(error.contains("Circular include: workflow.json -> common.yaml -> common.yaml"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_workflow_registry_versions() {
+        let dir = temp_workflow_dir();
+        let path = write_workflow(
+            &dir,
+            serde_json::json!([command_step("say", "echo", &["$release"], &[])]),
+            serde_json::json!({}),
+        );
+        let base: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        for (file, name, version) in [
+            ("deploy_1_0.json", "deploy", "1.0.0"),
+            ("deploy_1_10.json", "deploy", "1.10.0"),
+            ("deploy_1_2.json", "deploy", "1.2.0"),
+            ("deploy_2_beta.json", "deploy", "2.0.0-beta.1"),
+            ("deploy_copy.json", "deploy", "1.2.0"),
+            ("other.json", "other", "1.0"),
+        ] {
+            let mut workflow = base.clone();
+            workflow["name"] = serde_json::json!(name);
+            workflow["version"] = serde_json::json!(version);
+            workflow["variables"] = serde_json::json!({ "release": version });
+            std::fs::write(dir.join(file), workflow.to_string()).unwrap();
+        }
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+
+        let engine = test_engine(&dir);
+        let registry = engine.registry().await.unwrap();
+        let versions: Vec<String> = registry.versions("deploy").iter().map(|w| w.version.to_string()).collect();
+        assert_eq!(versions, ["1.0.0", "1.2.0", "1.10.0", "2.0.0-beta.1"]);
+        assert_eq!(registry.find("deploy").unwrap().version.to_string(), "1.10.0");
+        assert!(registry.find("deploy@1.2.0").unwrap().path.ends_with("deploy_1_2.json"));
+        let error = registry.find("deploy@3.0.0").unwrap_err().to_string();
+        assert!(error.contains("available: 1.0.0, 1.2.0, 1.10.0, 2.0.0-beta.1"), "{}", error);
+        assert!(registry.find("missing").is_err());
+
+        let problems: Vec<String> = registry.issues.iter().map(|issue| issue.to_string()).collect();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].contains("broken.json") && problems[0].contains("Invalid JSON workflow"));
+        assert!(problems[1].contains("deploy_copy.json") && problems[1].contains("already defined"));
+        assert!(problems[2].contains("other.json") && problems[2].contains("not a semver version"));
+
+        let workflow_path = engine.resolve_workflow("deploy").await.unwrap();
+        let execution = engine.execute_workflow(&workflow_path).await.unwrap();
+        assert_eq!(execution.variables["steps.say.output"], "1.10.0");
+        let record = engine.get_execution(execution.id).await.unwrap().unwrap();
+        assert_eq!(record.workflow_version.as_deref(), Some("1.10.0"));
+        assert_eq!(record.content_hash.as_deref(), Some(registry.find("deploy").unwrap().content_hash.as_str()));
+
+        // Another workflow reusing an id is reported
+        let mut clash = base.clone();
+        clash["name"] = serde_json::json!("clash");
+        std::fs::write(dir.join("z_clash.json"), clash.to_string()).unwrap();
+        let registry = engine.registry().await.unwrap();
+        assert!(registry.issues.last().unwrap().message.contains("is also used by 'deploy'"));
+    }
+
+    #[tokio::test]
+    async fn test_approval_gate_waits_for_decision() {
+        let dir = temp_workflow_dir();
+        let mut gate = command_step("gate", "", &["Delete production data for $target?"], &[]);
+        gate["step_type"] = serde_json::json!("Approval");
+        gate["timeout"] = serde_json::json!(null);
+        gate["approval"] = serde_json::json!({ "approvers": ["alice"] });
+        let delete = command_step("delete", "echo", &["approved by ${steps.gate.approver}"], &["gate"]);
+        let variables = serde_json::json!({ "variables": { "target": "orders" } });
+        let path = write_workflow(&dir, serde_json::json!([gate, delete]), variables.clone());
+        let engine = test_engine(&dir);
+
+        let approve = async {
+            let execution_id = loop {
+                let records: Vec<PathBuf> = std::fs::read_dir(dir.join("executions"))
+                    .map(|entries| entries.map(|entry| entry.unwrap().path()).filter(|path| path.is_file()).collect())
+                    .unwrap_or_default();
+                let id = records.first().and_then(|path| path.file_stem()?.to_str()?.parse::<uuid::Uuid>().ok());
+                if let Some(id) = id.filter(|id| !engine.pending_approvals(*id).unwrap().is_empty()) {
+                    break id;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            };
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            let waiting = engine.get_execution(execution_id).await.unwrap().unwrap();
+            assert!(matches!(waiting.status, ExecutionStatus::WaitingForApproval));
+            assert!(matches!(waiting.steps_executed[0].status, ExecutionStatus::WaitingForApproval));
+            let pending = engine.pending_approvals(execution_id).unwrap();
+            assert_eq!(pending[0].message, "Delete production data for orders?");
+
+            let error = engine
+                .decide_approval(execution_id, "gate", ApprovalDecision::Approved, "bob", None)
+                .unwrap_err();
+            assert!(error.to_string().contains("approvers are alice"));
+            engine
+                .decide_approval(execution_id, "gate", ApprovalDecision::Approved, "alice", Some("checked backups"))
+                .unwrap();
+            assert!(engine
+                .decide_approval(execution_id, "gate", ApprovalDecision::Rejected, "alice", None)
+                .is_err());
+        };
+        let (execution, ()) = tokio::join!(engine.execute_workflow(&path), approve);
+        let execution = execution.unwrap();
+        let approval = execution.steps_executed[0].approval.clone().unwrap();
+        assert_eq!(approval.approver.as_deref(), Some("alice"));
+        assert_eq!(approval.comment.as_deref(), Some("checked backups"));
+        assert_eq!(execution.variables["steps.delete.output"], "approved by alice");
+
+        // An expired gate takes its on_timeout decision
+        gate["timeout"] = serde_json::json!(1);
+        gate["approval"] = serde_json::json!({ "on_timeout": "Rejected" });
+        write_workflow(&dir, serde_json::json!([gate, delete]), variables);
+        let error = engine.execute_workflow(&path).await.unwrap_err();
+        assert!(error.to_string().contains("Rejected after the approval timed out"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_approval_gate_does_not_hold_a_slot() {
+        let dir = temp_workflow_dir();
+        let mut gate = command_step("gate", "", &["Ship it?"], &[]);
+        gate["step_type"] = serde_json::json!("Approval");
+        gate["timeout"] = serde_json::json!(1);
+        gate["approval"] = serde_json::json!({ "on_timeout": "Approved" });
+        let path = write_workflow(
+            &dir,
+            serde_json::json!([gate, command_step("build", "true", &[], &[])]),
+            serde_json::json!({ "max_parallel_steps": 1 }),
+        );
+        let engine = WorkflowEngine::new(WorkflowConfig {
+            workflow_dir: dir.clone(),
+            max_parallel_steps: 1,
+            ..WorkflowConfig::default()
+        });
+
+        // With one slot, `build` finishes while the gate is still waiting
+        let execution = engine.execute_workflow(&path).await.unwrap();
+        let completed_at = |id: &str| {
+            let step = execution.steps_executed.iter().find(|step| step.step_id == id).unwrap();
+            step.completed_at.unwrap()
+        };
+        assert!(completed_at("build") < completed_at("gate") - chrono::Duration::milliseconds(500));
+    }
+
+    #[tokio::test]
+    async fn test_approval_and_wait_steps_cannot_be_cached() {
+        let dir = temp_workflow_dir();
+        let mut gate = command_step("gate", "", &["Ship it?"], &[]);
+        gate["step_type"] = serde_json::json!("Approval");
+        gate["cache"] = serde_json::json!({});
+        let mut pause = command_step("pause", "duration", &["0.1"], &[]);
+        pause["step_type"] = serde_json::json!("Wait");
+        pause["cache"] = serde_json::json!({});
+        let engine = test_engine(&dir);
+
+        // A cached gate would hand back its stored decision instead of waiting for a new one
+        for (step, message) in [(gate, "Approval steps cannot be cached"), (pause, "Wait steps cannot be cached")] {
+            let workflow_path = write_workflow(&dir, serde_json::json!([step]), serde_json::json!({}));
+            let error = engine.execute_workflow(&workflow_path).await.unwrap_err();
+            assert!(error.to_string().contains(message), "{}", error);
+            let issues = engine.validate_workflow(&workflow_path).await.unwrap();
+            assert!(issues.iter().any(|issue| issue.location == "steps[0].cache" && issue.message == message));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_wait_is_rejected_when_loading() {
+        let dir = temp_workflow_dir();
+        let mut pause = command_step("pause", "sleep", &["0.1"], &[]);
+        pause["step_type"] = serde_json::json!("Wait");
+        let workflow_path = write_workflow(&dir, serde_json::json!([pause]), serde_json::json!({}));
+        let engine = test_engine(&dir);
+
+        // Loading and validate share the step rules, so both report the typo
+        let error = engine.execute_workflow(&workflow_path).await.unwrap_err();
+        assert!(error.to_string().contains("unknown wait 'sleep'"), "{}", error);
+        assert!(!dir.join("executions").exists());
+        let issues = engine.validate_workflow(&workflow_path).await.unwrap();
+        assert!(issues.iter().any(|issue| issue.location == "steps[0].command" && issue.message.starts_with("unknown wait 'sleep'")));
+    }
+
+    #[tokio::test]
+    async fn test_wait_steps_poll_without_holding_a_slot() {
+        let dir = temp_workflow_dir();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let sensor = |id: &str, command: &str, args: &[&str]| {
+            let mut step = command_step(id, command, args, &[]);
+            step["step_type"] = serde_json::json!("Wait");
+            step["wait"] = serde_json::json!({ "poll_interval_ms": 20 });
+            step
+        };
+        let ready = dir.join("ready").display().to_string();
+        let parts = dir.join("*.part").display().to_string();
+        let script = format!("sleep 0.2; touch {0} {1}/a.part {1}/b.part", ready, dir.display());
+        let path = write_workflow(
+            &dir,
+            serde_json::json!([
+                sensor("ready", "file", &[&ready]),
+                sensor("parts", "glob", &[&parts, "2"]),
+                sensor("probe", "command", &["test", "-f", &ready]),
+                sensor("port", "port", &[&address]),
+                sensor("pause", "duration", &["0.1"]),
+                command_step("produce", "sh", &["-c", &script], &[]),
+            ]),
+            serde_json::json!({ "max_parallel_steps": 1 }),
+        );
+        let engine = WorkflowEngine::new(WorkflowConfig {
+            workflow_dir: dir.clone(),
+            max_parallel_steps: 1,
+            ..WorkflowConfig::default()
+        });
+
+        // With one slot, `produce` only runs if the sensors leave it free
+        let execution = engine.execute_workflow(&path).await.unwrap();
+        assert!(execution.steps_executed.iter().all(|step| matches!(step.status, ExecutionStatus::Completed)));
+        assert_eq!(execution.variables["steps.parts.count"], "2");
+        assert_eq!(execution.variables["steps.port.output"], format!("{} accepts connections", address));
+        assert_ne!(execution.variables["steps.ready.polls"], "0");
+
+        // The step's timeout ends a wait that is never satisfied, without retrying it
+        let dir = temp_workflow_dir();
+        let never = dir.join("never").display().to_string();
+        let mut missing = sensor("missing", "file", &[&never]);
+        missing["timeout"] = serde_json::json!(1);
+        let path = write_workflow(&dir, serde_json::json!([missing]), serde_json::json!({}));
+        let engine = test_engine(&dir);
+        let error = engine.execute_workflow(&path).await.unwrap_err();
+        assert!(error.to_string().contains("timed out after 1s"), "{}", error);
+        let record = engine.get_execution(only_execution_id(&dir)).await.unwrap().unwrap();
+        assert!(matches!(record.status, ExecutionStatus::TimedOut));
+        assert_eq!(record.steps_executed[0].attempts.len(), 1);
     }
 }
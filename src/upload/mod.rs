@@ -313,7 +313,7 @@ impl UploadManager {
             for entry in WalkDir::new(&records_dir)
                 .into_iter()
                 .filter_map(|e| e.ok())
-                .filter(|e| e.path().extension().map_or(false, |ext| ext == "json"))
+                .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
             {
                 if let Ok(content) = fs::read_to_string(entry.path()) {
                     if let Ok(upload_info) = serde_json::from_str::<UploadInfo>(&content) {
@@ -1,11 +1,9 @@
-use anyhow::Result;
-use std::path::{Path, PathBuf};
-use tracing::info;
-
 pub mod file_utils;
 pub mod validation;
 pub mod compression;
+pub mod retry;
 
 pub use file_utils::*;
 pub use validation::*;
 pub use compression::*;
+pub use retry::*;
@@ -0,0 +1,150 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How long to wait between attempts of a failing step and which failures
+/// are worth retrying at all. The number of retries itself comes from
+/// `WorkflowStep.retry_count` or `WorkflowConfig.retry_attempts`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    #[serde(default)]
+    pub backoff: Backoff,
+    /// Delay before the first retry; later retries scale it according to `backoff`.
+    #[serde(default = "default_delay_ms")]
+    pub delay_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_delay_ms: Option<u64>,
+    /// Randomizes each delay by up to this fraction (0.0 - 1.0) in either direction.
+    #[serde(default)]
+    pub jitter: f64,
+    /// Only retry when the process exited with one of these codes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retry_on_exit_codes: Vec<i32>,
+    /// Only retry when stderr (or the error message) matches one of these regexes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retry_on_stderr: Vec<StderrPattern>,
+    /// Stop retrying once this long has passed since the first attempt started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retry_duration_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Backoff {
+    Fixed,
+    Linear,
+    #[default]
+    Exponential,
+}
+
+/// A `retry_on_stderr` regex, compiled once when the policy is deserialized
+/// so an invalid pattern fails the config or workflow that declares it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct StderrPattern(Regex);
+
+impl StderrPattern {
+    pub fn new(pattern: &str) -> Result<Self> {
+        Regex::new(pattern)
+            .map(Self)
+            .map_err(|e| anyhow::anyhow!("Invalid retry_on_stderr pattern '{}': {}", pattern, e))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl PartialEq for StderrPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl TryFrom<String> for StderrPattern {
+    type Error = anyhow::Error;
+
+    fn try_from(pattern: String) -> Result<Self> {
+        Self::new(&pattern)
+    }
+}
+
+impl From<StderrPattern> for String {
+    fn from(pattern: StderrPattern) -> Self {
+        pattern.as_str().to_string()
+    }
+}
+
+fn default_delay_ms() -> u64 {
+    1000
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            backoff: Backoff::Exponential,
+            delay_ms: default_delay_ms(),
+            max_delay_ms: None,
+            jitter: 0.0,
+            retry_on_exit_codes: Vec::new(),
+            retry_on_stderr: Vec::new(),
+            max_retry_duration_seconds: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Checks the policy's ranges so mistakes surface when the config or
+    /// workflow loads. The stderr patterns were already compiled by then.
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(anyhow::anyhow!("Retry jitter must be between 0.0 and 1.0, got {}", self.jitter));
+        }
+        Ok(())
+    }
+
+    /// Whether a failed attempt should be retried. Without any filters every
+    /// failure is retryable; otherwise a matching exit code or stderr pattern is.
+    pub fn is_retryable(&self, exit_code: Option<i32>, stderr: &str) -> bool {
+        if self.retry_on_exit_codes.is_empty() && self.retry_on_stderr.is_empty() {
+            return true;
+        }
+
+        let code_matches = exit_code.is_some_and(|code| self.retry_on_exit_codes.contains(&code));
+        let stderr_matches = self.retry_on_stderr.iter().any(|pattern| pattern.0.is_match(stderr));
+        code_matches || stderr_matches
+    }
+
+    /// Delay before retry number `retry` (starting at 1).
+    pub fn delay(&self, retry: u32) -> Duration {
+        let base = self.delay_ms as f64;
+        let mut delay = match self.backoff {
+            Backoff::Fixed => base,
+            Backoff::Linear => base * retry as f64,
+            Backoff::Exponential => base * 2f64.powi(retry.saturating_sub(1).min(62) as i32),
+        };
+        if let Some(max_delay_ms) = self.max_delay_ms {
+            delay = delay.min(max_delay_ms as f64);
+        }
+        if self.jitter > 0.0 {
+            // A v4 UUID is 122 random bits, plenty for spreading retries apart
+            let unit = (Uuid::new_v4().as_u128() >> 64) as f64 / u64::MAX as f64;
+            delay *= 1.0 + self.jitter * (unit * 2.0 - 1.0);
+        }
+        Duration::from_millis(delay.max(0.0) as u64)
+    }
+
+    /// Whether another retry that waits `delay` still fits in the total retry
+    /// deadline, measured from `first_started`.
+    pub fn within_deadline(&self, first_started: DateTime<Utc>, delay: Duration) -> bool {
+        match self.max_retry_duration_seconds {
+            Some(limit) => {
+                let elapsed = (Utc::now() - first_started).to_std().unwrap_or_default();
+                elapsed + delay < Duration::from_secs(limit)
+            }
+            None => true,
+        }
+    }
+}
@@ -52,7 +52,6 @@ pub fn validate_file_writable(path: &Path) -> Result<()> {
     if path.exists() {
         // Try to open for writing
         let file = std::fs::OpenOptions::new()
-            .write(true)
             .append(true)
             .open(path)?;
         drop(file);
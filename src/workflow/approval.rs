@@ -0,0 +1,284 @@
+use super::{StepOutput, WorkflowEngine, WorkflowExecution, WorkflowStep};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// How often a waiting gate checks for a decision, which may come from another process.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Settings of an `Approval` step. The step's `timeout` bounds the wait; without
+/// one the gate waits until it is decided, cancelled or the workflow deadline passes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApprovalSpec {
+    /// Who may decide; anyone when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub approvers: Vec<String>,
+    /// The decision taken when the timeout passes without one.
+    #[serde(default)]
+    pub on_timeout: ApprovalDecision,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ApprovalDecision {
+    Approved,
+    #[default]
+    Rejected,
+}
+
+/// The outcome of an approval gate, kept in its `StepExecution`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApprovalRecord {
+    pub decision: ApprovalDecision,
+    /// Unset when the decision was `on_timeout`.
+    pub approver: Option<String>,
+    pub comment: Option<String>,
+    pub decided_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub timed_out: bool,
+}
+
+/// An open (or decided) gate, stored as
+/// `executions/<execution id>/approvals/<step id>.json` so that any process can
+/// decide it through `WorkflowEngine::decide_approval`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalRequest {
+    pub execution_id: Uuid,
+    pub step_id: String,
+    pub step_name: String,
+    /// The step's joined `args`, shown to approvers.
+    pub message: String,
+    pub approvers: Vec<String>,
+    pub requested_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub decision: Option<ApprovalRecord>,
+}
+
+impl WorkflowEngine {
+    pub fn approvals_dir(&self, execution_id: Uuid) -> PathBuf {
+        self.config
+            .workflow_dir
+            .join("executions")
+            .join(execution_id.to_string())
+            .join("approvals")
+    }
+
+    /// Approves or rejects a gate that is waiting in `execution_id`, whether the
+    /// execution runs in this process or another one.
+    pub fn decide_approval(
+        &self,
+        execution_id: Uuid,
+        step_id: &str,
+        decision: ApprovalDecision,
+        approver: &str,
+        comment: Option<&str>,
+    ) -> Result<ApprovalRequest> {
+        let path = self.approval_path(execution_id, step_id);
+        let not_waiting =
+            || anyhow::anyhow!("Step {} of execution {} is not waiting for approval", step_id, execution_id);
+        if !path.exists() {
+            return Err(not_waiting());
+        }
+        let _lock = RequestLock::acquire(&path)?;
+        let mut request = read_request(&path)?.ok_or_else(not_waiting)?;
+        if let Some(existing) = &request.decision {
+            return Err(anyhow::anyhow!(
+                "Step {} of execution {} was already {:?}",
+                step_id,
+                execution_id,
+                existing.decision
+            ));
+        }
+        if !request.approvers.is_empty() && !request.approvers.iter().any(|allowed| allowed == approver) {
+            return Err(anyhow::anyhow!(
+                "{} may not decide step {}; approvers are {}",
+                approver,
+                step_id,
+                request.approvers.join(", ")
+            ));
+        }
+
+        request.decision = Some(ApprovalRecord {
+            decision,
+            approver: Some(approver.to_string()),
+            comment: comment.map(str::to_string),
+            decided_at: Utc::now(),
+            timed_out: false,
+        });
+        write_request(&path, &request)?;
+        info!("Step {} of execution {} {:?} by {}", step_id, execution_id, decision, approver);
+        Ok(request)
+    }
+
+    /// Gates of `execution_id` still waiting for a decision.
+    pub fn pending_approvals(&self, execution_id: Uuid) -> Result<Vec<ApprovalRequest>> {
+        let dir = self.approvals_dir(execution_id);
+        let mut pending = Vec::new();
+        if dir.exists() {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().is_none_or(|extension| extension != "json") {
+                    continue;
+                }
+                if let Some(request) = read_request(&path)? {
+                    if request.decision.is_none() {
+                        pending.push(request);
+                    }
+                }
+            }
+        }
+        pending.sort_by_key(|request| request.requested_at);
+        Ok(pending)
+    }
+
+    /// Opens the gate of an `Approval` step and waits for its decision. A
+    /// decision already recorded, e.g. while a resumed execution was down, is used as is.
+    pub(super) async fn approval_step(&self, step: &WorkflowStep, execution: &WorkflowExecution) -> Result<StepOutput> {
+        let spec = step.approval.clone().unwrap_or_default();
+        let path = self.approval_path(execution.id, &step.id);
+        let timeout = step
+            .timeout
+            .as_ref()
+            .map(|timeout| timeout.resolve(&execution.variables))
+            .transpose()?
+            .map(Duration::from_secs);
+
+        fs::create_dir_all(self.approvals_dir(execution.id))?;
+        let lock = RequestLock::acquire(&path)?;
+        let request = match read_request(&path)? {
+            Some(request) if request.decision.is_some() => request,
+            _ => {
+                let request = ApprovalRequest {
+                    execution_id: execution.id,
+                    step_id: step.id.clone(),
+                    step_name: step.name.clone(),
+                    message: execution.secrets.redact(&step.args.join(" ")),
+                    approvers: spec.approvers.clone(),
+                    requested_at: Utc::now(),
+                    expires_at: timeout
+                        .map(|timeout| Utc::now() + chrono::Duration::from_std(timeout).unwrap_or_default()),
+                    decision: None,
+                };
+                write_request(&path, &request)?;
+                info!(
+                    "Step {} of execution {} is waiting for approval: {}",
+                    step.id, execution.id, request.message
+                );
+                request
+            }
+        };
+        drop(lock);
+
+        let record = match request.decision {
+            Some(record) => record,
+            None => {
+                let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+                loop {
+                    match read_request(&path) {
+                        Ok(Some(ApprovalRequest { decision: Some(record), .. })) => break record,
+                        Ok(_) => {}
+                        Err(e) => warn!("Failed to read approval request {}: {}", path.display(), e),
+                    }
+                    if deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline) {
+                        // A decision made since the last poll wins over the timeout
+                        let _lock = RequestLock::acquire(&path)?;
+                        let mut request = read_request(&path)?.unwrap_or_else(|| request.clone());
+                        if let Some(record) = request.decision {
+                            break record;
+                        }
+                        warn!("Approval of step {} timed out; treating it as {:?}", step.id, spec.on_timeout);
+                        let record = ApprovalRecord {
+                            decision: spec.on_timeout,
+                            approver: None,
+                            comment: None,
+                            decided_at: Utc::now(),
+                            timed_out: true,
+                        };
+                        request.decision = Some(record.clone());
+                        write_request(&path, &request)?;
+                        break record;
+                    }
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        };
+        Ok(approval_output(record))
+    }
+
+    fn approval_path(&self, execution_id: Uuid, step_id: &str) -> PathBuf {
+        self.approvals_dir(execution_id).join(format!("{}.json", step_id))
+    }
+}
+
+/// A rejection fails the step; either way the decision is published as
+/// `steps.<id>.decision`, `.approver` and `.comment`.
+fn approval_output(record: ApprovalRecord) -> StepOutput {
+    let decided_by = match &record.approver {
+        Some(approver) => format!("{:?} by {}", record.decision, approver),
+        None => format!("{:?} after the approval timed out", record.decision),
+    };
+    let summary = match &record.comment {
+        Some(comment) => format!("{}: {}", decided_by, comment),
+        None => decided_by,
+    };
+
+    let mut variables = HashMap::new();
+    variables.insert("decision".to_string(), format!("{:?}", record.decision));
+    variables.insert("approver".to_string(), record.approver.clone().unwrap_or_default());
+    variables.insert("comment".to_string(), record.comment.clone().unwrap_or_default());
+    let approved = record.decision == ApprovalDecision::Approved;
+    StepOutput {
+        stdout: if approved { summary.clone() } else { String::new() },
+        stderr: if approved { String::new() } else { summary },
+        exit_code: Some(if approved { 0 } else { 1 }),
+        variables,
+        child_execution_id: None,
+        approval: Some(record),
+    }
+}
+
+fn read_request(path: &Path) -> Result<Option<ApprovalRequest>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+}
+
+/// Writes through a temporary file so a polling gate never reads half a request.
+fn write_request(path: &Path, request: &ApprovalRequest) -> Result<()> {
+    let temporary = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+    fs::write(&temporary, serde_json::to_string_pretty(request)?)?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// An exclusive lock on a gate's `<step id>.lock` file, held while its request
+/// is read and rewritten so that a decision and the timeout cannot overwrite
+/// each other, even when they come from different processes. Dropping it
+/// closes the file, which releases the lock.
+struct RequestLock {
+    _file: fs::File,
+}
+
+impl RequestLock {
+    fn acquire(request: &Path) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(request.with_extension("lock"))?;
+        // SAFETY: flock(2) only operates on the descriptor, which `file` keeps open
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self { _file: file })
+    }
+}
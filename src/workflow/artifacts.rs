@@ -0,0 +1,222 @@
+use super::{WorkflowEngine, WorkflowStep};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// A file a step declared under `artifacts`, copied out of its working
+/// directory once the step succeeded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Artifact {
+    /// File name, unique within the step.
+    pub name: String,
+    pub step_id: String,
+    /// Where the step wrote the file.
+    pub source: String,
+    /// The stored copy, under `workflow_dir/executions/<id>/artifacts/<step_id>/`.
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+    pub collected_at: DateTime<Utc>,
+}
+
+/// Collecting and managing the files steps declare as `artifacts`. Later steps
+/// reach a step's artifacts through `steps.<id>.artifacts` (stored paths, one per
+/// line) and `steps.<id>.artifacts.<name>`.
+impl WorkflowEngine {
+    /// Where the artifacts of an execution are stored.
+    pub fn artifacts_dir(&self, execution_id: Uuid) -> PathBuf {
+        self.config
+            .workflow_dir
+            .join("executions")
+            .join(execution_id.to_string())
+            .join("artifacts")
+    }
+
+    /// The artifacts recorded for an execution, in step order.
+    pub async fn list_artifacts(&self, execution_id: Uuid) -> Result<Vec<Artifact>> {
+        let execution = self
+            .get_execution(execution_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Execution {} not found", execution_id))?;
+        Ok(execution
+            .steps_executed
+            .into_iter()
+            .flat_map(|step_execution| step_execution.artifacts)
+            .collect())
+    }
+
+    /// Path of a stored artifact, after checking it still matches its recorded checksum.
+    pub async fn get_artifact(&self, execution_id: Uuid, step_id: &str, name: &str) -> Result<PathBuf> {
+        let artifact = self
+            .list_artifacts(execution_id)
+            .await?
+            .into_iter()
+            .find(|artifact| artifact.step_id == step_id && artifact.name == name)
+            .ok_or_else(|| {
+                anyhow::anyhow!("Execution {} has no artifact {} from step {}", execution_id, name, step_id)
+            })?;
+        if !artifact.path.is_file() {
+            return Err(anyhow::anyhow!("Artifact {} is no longer stored", artifact.path.display()));
+        }
+        if sha256_file(&artifact.path)? != artifact.sha256 {
+            return Err(anyhow::anyhow!("Artifact {} does not match its checksum", artifact.path.display()));
+        }
+        Ok(artifact.path)
+    }
+
+    /// Deletes the stored artifacts of a finished execution and returns the
+    /// number of bytes freed. The execution record is kept.
+    pub async fn remove_artifacts(&self, execution_id: Uuid) -> Result<u64> {
+        if self.active_executions.contains_key(&execution_id) {
+            return Err(anyhow::anyhow!("Execution {} is still running", execution_id));
+        }
+        let dir = self.artifacts_dir(execution_id);
+        if !dir.exists() {
+            return Ok(0);
+        }
+        let freed = dir_size(&dir)?;
+        fs::remove_dir_all(&dir)?;
+        if let Some(execution_dir) = dir.parent() {
+            // Only removed once nothing else is stored for the execution
+            let _ = fs::remove_dir(execution_dir);
+        }
+        info!("Removed {} bytes of artifacts of execution {}", freed, execution_id);
+        Ok(freed)
+    }
+
+    /// Removes the artifacts of executions that finished before `cutoff`, or
+    /// whose record no longer exists. Returns the number of executions cleaned up.
+    pub async fn cleanup_artifacts(&self, cutoff: DateTime<Utc>) -> Result<usize> {
+        let executions_dir = self.config.workflow_dir.join("executions");
+        if !executions_dir.exists() {
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+        for entry in fs::read_dir(&executions_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let Ok(execution_id) = entry.file_name().to_string_lossy().parse::<Uuid>() else {
+                continue;
+            };
+            if self.active_executions.contains_key(&execution_id) {
+                continue;
+            }
+            let expired = match self.get_execution(execution_id).await {
+                Ok(Some(execution)) => execution.completed_at.is_some_and(|completed| completed < cutoff),
+                Ok(None) => true,
+                Err(e) => {
+                    warn!("Skipping artifacts of execution {}: {}", execution_id, e);
+                    false
+                }
+            };
+            if expired {
+                self.remove_artifacts(execution_id).await?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Copies the files matching a succeeded step's `artifacts` patterns into
+    /// the execution's artifacts directory. A pattern matching no file fails the step.
+    pub(super) async fn collect_artifacts(&self, step: &WorkflowStep, execution_id: Uuid) -> Result<Vec<Artifact>> {
+        // Copying and hashing large files would stall a runtime worker
+        let dir = self.artifacts_dir(execution_id).join(&step.id);
+        let step = step.clone();
+        tokio::task::spawn_blocking(move || collect(&step, &dir)).await?
+    }
+}
+
+fn collect(step: &WorkflowStep, dir: &Path) -> Result<Vec<Artifact>> {
+    let base = step.working_dir.as_deref().map(Path::new).unwrap_or(Path::new("."));
+    let mut sources = Vec::new();
+    for pattern in &step.artifacts {
+        let full_pattern = base.join(pattern).display().to_string();
+        let mut matched: Vec<PathBuf> = glob::glob(&full_pattern)
+            .map_err(|e| anyhow::anyhow!("Invalid artifact pattern '{}': {}", pattern, e))?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|path| path.is_file())
+            .collect();
+        if matched.is_empty() {
+            return Err(anyhow::anyhow!("Artifact '{}' matched no files", pattern));
+        }
+        matched.sort();
+        sources.extend(matched);
+    }
+
+    fs::create_dir_all(dir)?;
+    let mut artifacts: Vec<Artifact> = Vec::new();
+    for source in sources {
+        let name = source
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| anyhow::anyhow!("Artifact {} has no file name", source.display()))?;
+        if artifacts.iter().any(|artifact| artifact.name == name) {
+            return Err(anyhow::anyhow!("Step {} declares two artifacts named {}", step.id, name));
+        }
+
+        let path = dir.join(&name);
+        let size = fs::copy(&source, &path)
+            .map_err(|e| anyhow::anyhow!("Cannot copy artifact {}: {}", source.display(), e))?;
+        artifacts.push(Artifact {
+            name,
+            step_id: step.id.clone(),
+            source: source.display().to_string(),
+            sha256: sha256_file(&path)?,
+            path,
+            size,
+            collected_at: Utc::now(),
+        });
+    }
+
+    info!("Collected {} artifacts from step {}", artifacts.len(), step.id);
+    Ok(artifacts)
+}
+
+/// Variables describing a step's artifacts, published under `steps.<id>.`.
+pub(super) fn artifact_variables(artifacts: &[Artifact]) -> HashMap<String, String> {
+    let mut variables: HashMap<String, String> = artifacts
+        .iter()
+        .map(|artifact| (format!("artifacts.{}", artifact.name), artifact.path.display().to_string()))
+        .collect();
+    let paths: Vec<String> = artifacts.iter().map(|artifact| artifact.path.display().to_string()).collect();
+    variables.insert("artifacts".to_string(), paths.join("\n"));
+    variables
+}
+
+pub(super) fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() { dir_size(&entry.path())? } else { metadata.len() };
+    }
+    Ok(size)
+}
@@ -0,0 +1,301 @@
+use super::{
+    artifacts::{self, Artifact},
+    StepExecution, WorkflowEngine, WorkflowExecution, WorkflowStep,
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Opts a step into result caching. The cache key covers the interpolated step
+/// definition plus whatever is listed here, e.g.
+/// `{"files": ["$input_file"], "env": ["LANG"]}`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CacheSpec {
+    /// Files (paths or globs, relative to `working_dir`) whose contents are part of the key.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+    /// Environment variables whose values are part of the key, looked up the way
+    /// the step sees them: its own `env`, then the workflow variables and secrets
+    /// exported to it, then the orchestrator's environment.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    /// Entries older than this are treated as misses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_seconds: Option<u64>,
+}
+
+/// Results of cached steps, stored under `SystemConfig.cache_dir` and kept
+/// below `max_size` bytes by evicting the least recently used entries.
+#[derive(Debug)]
+pub struct StepCache {
+    dir: PathBuf,
+    max_size: u64,
+    /// Serializes writes and eviction between parallel steps.
+    lock: Mutex<()>,
+}
+
+/// What a cached step produced: everything `StepExecution` needs to be
+/// restored, with its artifacts stored next to `entry.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub key: String,
+    pub step_id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    /// Bytes used by the entry, including its artifacts.
+    pub size: u64,
+    pub output: Option<String>,
+    pub exit_code: Option<i32>,
+    pub variables: HashMap<String, String>,
+    pub artifacts: Vec<String>,
+    /// Where the step wrote each artifact, by name; a hit writes them there again.
+    #[serde(default)]
+    pub sources: HashMap<String, String>,
+}
+
+/// A cache hit restored into an execution.
+pub(super) struct CachedResult {
+    pub entry: CacheEntry,
+    pub artifacts: Vec<Artifact>,
+}
+
+impl StepCache {
+    pub fn new(dir: PathBuf, max_size: u64) -> Self {
+        Self {
+            dir: dir.join("steps"),
+            max_size,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// The entry for `key`, if any and not older than `max_age_seconds`.
+    /// Marks it as recently used.
+    pub async fn lookup(&self, key: &str, max_age_seconds: Option<u64>) -> Result<Option<CacheEntry>> {
+        let _guard = self.lock.lock().await;
+        let entry_path = self.entry_dir(key).join("entry.json");
+        if !entry_path.exists() {
+            return Ok(None);
+        }
+        let mut entry: CacheEntry = serde_json::from_str(&fs::read_to_string(&entry_path)?)?;
+        if let Some(max_age) = max_age_seconds {
+            if (Utc::now() - entry.created_at).num_seconds() > max_age as i64 {
+                return Ok(None);
+            }
+        }
+        entry.last_used_at = Utc::now();
+        fs::write(&entry_path, serde_json::to_string_pretty(&entry)?)?;
+        Ok(Some(entry))
+    }
+
+    /// Stores a step's result and its artifact files, then evicts entries until
+    /// the cache fits in `max_size`.
+    pub async fn store(&self, mut entry: CacheEntry, artifacts: &[Artifact]) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let dir = self.entry_dir(&entry.key);
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        let artifacts_dir = dir.join("artifacts");
+        fs::create_dir_all(&artifacts_dir)?;
+
+        entry.size = entry.output.as_ref().map_or(0, |output| output.len() as u64);
+        for artifact in artifacts {
+            entry.size += fs::copy(&artifact.path, artifacts_dir.join(&artifact.name))?;
+        }
+        entry.artifacts = artifacts.iter().map(|artifact| artifact.name.clone()).collect();
+        entry.sources = artifacts
+            .iter()
+            .map(|artifact| (artifact.name.clone(), artifact.source.clone()))
+            .collect();
+        fs::write(dir.join("entry.json"), serde_json::to_string_pretty(&entry)?)?;
+
+        self.evict()
+    }
+
+    /// Removes every entry.
+    pub async fn clear(&self) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    /// Total bytes used by the cache.
+    pub fn size(&self) -> Result<u64> {
+        Ok(self.entries()?.iter().map(|entry| entry.size).sum())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.entry_dir(key).join("entry.json").exists()
+    }
+
+    fn entry_dir(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+
+    fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let mut entries = Vec::new();
+        if !self.dir.exists() {
+            return Ok(entries);
+        }
+        for dir in fs::read_dir(&self.dir)? {
+            let entry_path = dir?.path().join("entry.json");
+            match fs::read_to_string(&entry_path).map(|content| serde_json::from_str::<CacheEntry>(&content)) {
+                Ok(Ok(entry)) => entries.push(entry),
+                _ => warn!("Ignoring unreadable cache entry {}", entry_path.display()),
+            }
+        }
+        Ok(entries)
+    }
+
+    fn evict(&self) -> Result<()> {
+        let mut entries = self.entries()?;
+        let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+        entries.sort_by_key(|entry| entry.last_used_at);
+        for entry in entries {
+            if size <= self.max_size {
+                break;
+            }
+            fs::remove_dir_all(self.entry_dir(&entry.key))?;
+            size -= entry.size;
+            info!("Evicted cached result of step {} ({} bytes)", entry.step_id, entry.size);
+        }
+        Ok(())
+    }
+}
+
+impl WorkflowEngine {
+    /// The cache key of an interpolated step: a hash of its definition, the
+    /// environment variables and the contents of the files its `cache` lists.
+    pub(super) fn cache_key(
+        &self,
+        step: &WorkflowStep,
+        spec: &CacheSpec,
+        execution: &WorkflowExecution,
+    ) -> Result<String> {
+        let exported = execution.step_variables();
+        let env: BTreeMap<&String, Option<String>> = spec
+            .env
+            .iter()
+            .map(|name| {
+                let value = step.env.get(name).or_else(|| exported.get(name)).cloned();
+                (name, value.or_else(|| std::env::var(name).ok()))
+            })
+            .collect();
+
+        let base = step.working_dir.as_deref().map(Path::new).unwrap_or(Path::new("."));
+        let mut files = BTreeMap::new();
+        for pattern in &spec.files {
+            let full_pattern = base.join(pattern).display().to_string();
+            let mut matched = 0;
+            for path in glob::glob(&full_pattern)
+                .map_err(|e| anyhow::anyhow!("Invalid cache file pattern '{}': {}", pattern, e))?
+            {
+                let path = path?;
+                if path.is_file() {
+                    files.insert(path.display().to_string(), artifacts::sha256_file(&path)?);
+                    matched += 1;
+                }
+            }
+            if matched == 0 {
+                return Err(anyhow::anyhow!("Cache file '{}' matched no files", pattern));
+            }
+        }
+
+        let key = serde_json::json!({ "step": step, "env": env, "files": files });
+        Ok(format!("{:x}", Sha256::digest(key.to_string().as_bytes())))
+    }
+
+    /// Restores a cached result for `step`, copying its artifacts into the
+    /// execution and back to where the step wrote them, so later steps find them
+    /// as if the step had run.
+    pub(super) async fn restore_cached(
+        &self,
+        step: &WorkflowStep,
+        key: &str,
+        spec: &CacheSpec,
+        execution_id: Uuid,
+    ) -> Result<Option<CachedResult>> {
+        let Some(cache) = &self.cache else {
+            return Ok(None);
+        };
+        let Some(entry) = cache.lookup(key, spec.max_age_seconds).await? else {
+            return Ok(None);
+        };
+
+        let dir = self.artifacts_dir(execution_id).join(&step.id);
+        let mut artifacts = Vec::new();
+        for name in &entry.artifacts {
+            fs::create_dir_all(&dir)?;
+            let stored = cache.entry_dir(key).join("artifacts").join(name);
+            let path = dir.join(name);
+            let size = fs::copy(&stored, &path)?;
+            let source = match entry.sources.get(name) {
+                Some(source) => {
+                    if let Some(parent) = Path::new(source).parent().filter(|p| !p.as_os_str().is_empty()) {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::copy(&stored, source)?;
+                    source.clone()
+                }
+                None => stored.display().to_string(),
+            };
+            artifacts.push(Artifact {
+                name: name.clone(),
+                step_id: step.id.clone(),
+                source,
+                sha256: artifacts::sha256_file(&path)?,
+                path,
+                size,
+                collected_at: Utc::now(),
+            });
+        }
+        Ok(Some(CachedResult { entry, artifacts }))
+    }
+
+    /// Saves a completed step's result under `key`. Secrets are redacted first,
+    /// and a failure to store only costs the next run a cache miss.
+    pub(super) async fn store_cached(
+        &self,
+        step: &WorkflowStep,
+        key: String,
+        step_execution: &mut StepExecution,
+        execution: &WorkflowExecution,
+    ) {
+        let Some(cache) = &self.cache else {
+            return;
+        };
+        let entry = CacheEntry {
+            key: key.clone(),
+            step_id: step.id.clone(),
+            created_at: Utc::now(),
+            last_used_at: Utc::now(),
+            size: 0,
+            output: step_execution.output.as_deref().map(|output| execution.secrets.redact(output)),
+            exit_code: step_execution.exit_code,
+            // Artifact paths belong to this execution and are rebuilt on restore
+            variables: step_execution
+                .variables
+                .iter()
+                .filter(|(name, _)| *name != "artifacts" && !name.starts_with("artifacts."))
+                .map(|(name, value)| (name.clone(), execution.secrets.redact(value)))
+                .collect(),
+            artifacts: Vec::new(),
+            sources: HashMap::new(),
+        };
+        match cache.store(entry, &step_execution.artifacts).await {
+            Ok(()) => step_execution.cache_key = Some(key),
+            Err(e) => warn!("Failed to cache the result of step {}: {}", step.id, e),
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use super::{StepOutput, StepType, WorkflowEngine, WorkflowExecution, WorkflowStep};
+use crate::utils;
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tracing::info;
+
+/// Built-in semantics for each `StepType`. `args` hold the operands of the
+/// built-in steps, while `command` selects an operation where one applies:
+///
+/// - `Command`: runs `command` with `args`, or both as one script when `shell` is set.
+/// - `Script`: runs `command` as a `/bin/sh` script with `args` as `$1`, `$2`, ...
+/// - `Upload`: processes `args[0]` through the `UploadManager`.
+/// - `Download`: fetches `args[0]` (a path, `file://` or `http(s)://` URL) to `args[1]`.
+/// - `Transform`: `compress`, `decompress`, `archive` or `extract` via `utils::compression`;
+///   any other command runs as an external program.
+/// - `Validate`: applies the comma-separated checks in `command` to every path in `args`.
+/// - `Notify`: sends the joined `args` to `log` (the default) or `file:<path>`; any
+///   other command runs as an external program.
+/// - `Workflow`: runs the workflow file `command` (relative to `workflow_dir`) as a
+///   child execution, see `subworkflow.rs`.
+/// - `Approval`: waits until the step is approved or rejected, showing the joined
+///   `args` to approvers; a rejection fails it, see `approval.rs`.
+/// - `Wait`: polls until the sensor named by `command` (`file`, `file_stable`, `glob`,
+///   `command`, `port`, `duration` or `until`) is satisfied, see `sensor.rs`.
+impl WorkflowStep {
+    /// Whether the step runs `command` as an external program (or, with `shell`,
+    /// as a script) rather than a built-in operation. `Script` steps always run `/bin/sh`.
+    pub(super) fn runs_program(&self) -> bool {
+        match self.step_type {
+            StepType::Command => true,
+            StepType::Transform => !is_builtin_transform(&self.command),
+            StepType::Notify => !is_builtin_notify(&self.command),
+            _ => false,
+        }
+    }
+}
+
+fn is_builtin_transform(command: &str) -> bool {
+    matches!(command, "compress" | "decompress" | "archive" | "extract")
+}
+
+fn is_builtin_notify(command: &str) -> bool {
+    command.is_empty() || command == "log" || command.starts_with("file:")
+}
+
+impl WorkflowEngine {
+    pub(super) async fn dispatch_step(
+        &self,
+        step: &WorkflowStep,
+        execution: &WorkflowExecution,
+    ) -> Result<StepOutput> {
+        match step.step_type {
+            StepType::Command => self.run_step_command(step, execution).await,
+            StepType::Script => {
+                let mut args = vec!["-c".to_string(), step.command.clone(), step.id.clone()];
+                args.extend(step.args.iter().cloned());
+                self.run_command(step, "/bin/sh", &args, execution).await
+            }
+            StepType::Upload => self.upload_step(step).await,
+            StepType::Download => self.download_step(step, execution).await,
+            StepType::Transform => match step.command.as_str() {
+                "compress" | "decompress" | "archive" | "extract" => blocking(step, transform_step).await,
+                _ => self.run_step_command(step, execution).await,
+            },
+            StepType::Validate => blocking(step, validate_step).await,
+            StepType::Notify => match step.command.as_str() {
+                "" | "log" => {
+                    let message = step.args.join(" ");
+                    info!("Notification from step {}: {}", step.id, execution.secrets.redact(&message));
+                    Ok(StepOutput::builtin(message, HashMap::new()))
+                }
+                target if target.starts_with("file:") => {
+                    let message = step.args.join(" ");
+                    let path = Path::new(&target["file:".len()..]);
+                    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                        utils::ensure_directory_exists(parent)?;
+                    }
+                    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+                    writeln!(file, "{} [{}] {}", chrono::Utc::now().to_rfc3339(), step.id, message)?;
+                    Ok(StepOutput::builtin(message, HashMap::new()))
+                }
+                _ => self.run_step_command(step, execution).await,
+            },
+            StepType::Workflow => self.sub_workflow_step(step, execution).await,
+            StepType::Approval => self.approval_step(step, execution).await,
+            StepType::Wait => self.wait_step(step, execution).await,
+        }
+    }
+
+    async fn upload_step(&self, step: &WorkflowStep) -> Result<StepOutput> {
+        let upload_manager = self
+            .upload_manager
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Upload steps require an upload manager on the workflow engine"))?;
+        let path = operand(step, 0, "upload path")?;
+
+        let upload_info = upload_manager.process_upload(path).await?;
+
+        let mut variables = HashMap::new();
+        variables.insert("id".to_string(), upload_info.id.to_string());
+        variables.insert("filename".to_string(), upload_info.filename.clone());
+        variables.insert("processed_path".to_string(), upload_info.processed_path.display().to_string());
+        variables.insert("file_size".to_string(), upload_info.file_size.to_string());
+        variables.insert("mime_type".to_string(), upload_info.mime_type.clone());
+        variables.insert("checksum".to_string(), upload_info.metadata.checksum.clone());
+        if let Some(backup_path) = &upload_info.metadata.backup_path {
+            variables.insert("backup_path".to_string(), backup_path.display().to_string());
+        }
+        if let Some(ratio) = upload_info.metadata.compression_ratio {
+            variables.insert("compression_ratio".to_string(), format!("{:.2}", ratio));
+        }
+
+        Ok(StepOutput::builtin(upload_info.processed_path.display().to_string(), variables))
+    }
+
+    async fn download_step(&self, step: &WorkflowStep, execution: &WorkflowExecution) -> Result<StepOutput> {
+        let source = operand(step, 0, "download source")?;
+        let destination = PathBuf::from(operand(step, 1, "download destination")?);
+
+        // A directory destination keeps the source's file name
+        let target = if destination.is_dir() {
+            let name = source
+                .rsplit('/')
+                .find(|part| !part.is_empty())
+                .ok_or_else(|| anyhow::anyhow!("Cannot derive a file name from '{}'", source))?;
+            destination.join(utils::sanitize_filename(name))
+        } else {
+            destination
+        };
+        if let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) {
+            utils::ensure_directory_exists(parent)?;
+        }
+
+        if source.starts_with("http://") || source.starts_with("https://") {
+            let args = vec![
+                "-fsSL".to_string(),
+                "-o".to_string(),
+                target.display().to_string(),
+                source.to_string(),
+            ];
+            let output = self.run_command(step, "curl", &args, execution).await?;
+            if !output.success() {
+                return Err(anyhow::anyhow!("Download of {} failed: {}", source, output.stderr));
+            }
+        } else {
+            let path = Path::new(source.strip_prefix("file://").unwrap_or(source));
+            utils::validate_file_exists(path)?;
+            fs::copy(path, &target)?;
+        }
+
+        let mut variables = HashMap::new();
+        variables.insert("path".to_string(), target.display().to_string());
+        variables.insert("size".to_string(), utils::get_file_size(&target)?.to_string());
+        info!("Downloaded {} to {}", execution.secrets.redact(source), target.display());
+
+        Ok(StepOutput::builtin(target.display().to_string(), variables))
+    }
+}
+
+/// Runs a built-in step that does blocking file work on the blocking thread pool,
+/// so it does not stall a runtime worker and the step's timeout can still fail it.
+async fn blocking(step: &WorkflowStep, work: fn(&WorkflowStep) -> Result<StepOutput>) -> Result<StepOutput> {
+    let step = step.clone();
+    tokio::task::spawn_blocking(move || work(&step)).await?
+}
+
+fn transform_step(step: &WorkflowStep) -> Result<StepOutput> {
+    let mut variables = HashMap::new();
+
+    let output_path = match step.command.as_str() {
+        "compress" => {
+            let input = PathBuf::from(operand(step, 0, "input file")?);
+            let output = step
+                .args
+                .get(1)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(format!("{}.gz", input.display())));
+            let ratio = utils::compress_file_gzip(&input, &output)?;
+            variables.insert("compression_ratio".to_string(), format!("{:.2}", ratio));
+            output
+        }
+        "decompress" => {
+            let input = PathBuf::from(operand(step, 0, "input file")?);
+            let output = match step.args.get(1) {
+                Some(output) => PathBuf::from(output),
+                None if input.extension().is_some_and(|ext| ext == "gz") => input.with_extension(""),
+                None => return Err(anyhow::anyhow!("Step {} needs an output path for '{}'", step.id, input.display())),
+            };
+            utils::decompress_file_gzip(&input, &output)?;
+            output
+        }
+        "archive" => {
+            let output = PathBuf::from(operand(step, 0, "archive path")?);
+            let files: Vec<PathBuf> = step.args[1..].iter().map(PathBuf::from).collect();
+            if files.is_empty() {
+                return Err(anyhow::anyhow!("Step {} has no files to archive", step.id));
+            }
+            utils::create_zip_archive(&files, &output)?;
+            output
+        }
+        "extract" => {
+            let input = PathBuf::from(operand(step, 0, "archive path")?);
+            let output_dir = PathBuf::from(operand(step, 1, "output directory")?);
+            utils::ensure_directory_exists(&output_dir)?;
+            utils::extract_zip_archive(&input, &output_dir)?;
+            output_dir
+        }
+        other => return Err(anyhow::anyhow!("Unknown transform '{}'", other)),
+    };
+
+    variables.insert("path".to_string(), output_path.display().to_string());
+    Ok(StepOutput::builtin(output_path.display().to_string(), variables))
+}
+
+fn validate_step(step: &WorkflowStep) -> Result<StepOutput> {
+    let checks = if step.command.trim().is_empty() { "exists,readable" } else { step.command.as_str() };
+    if step.args.is_empty() {
+        return Err(anyhow::anyhow!("Step {} has no paths to validate", step.id));
+    }
+
+    for arg in &step.args {
+        let path = Path::new(arg);
+        for check in checks.split(',').map(str::trim).filter(|check| !check.is_empty()) {
+            match check.split_once('=') {
+                None if check == "exists" => utils::validate_file_exists(path)?,
+                None if check == "readable" => utils::validate_file_readable(path)?,
+                None if check == "writable" => utils::validate_file_writable(path)?,
+                None if check == "directory" => utils::validate_directory_exists(path)?,
+                Some(("max_size", limit)) => {
+                    let limit = limit
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid max_size '{}' in step {}", limit, step.id))?;
+                    utils::validate_file_size(path, limit)?
+                }
+                Some(("extension", allowed)) => {
+                    let allowed: Vec<String> = allowed.split('|').map(|ext| ext.to_lowercase()).collect();
+                    utils::validate_file_extension(path, &allowed)?
+                }
+                _ => return Err(anyhow::anyhow!("Unknown validation check '{}' in step {}", check, step.id)),
+            }
+        }
+    }
+
+    info!("Validated {} path(s) for step {}", step.args.len(), step.id);
+    Ok(StepOutput::builtin("success".to_string(), HashMap::new()))
+}
+
+fn operand<'a>(step: &'a WorkflowStep, index: usize, what: &str) -> Result<&'a str> {
+    step.args
+        .get(index)
+        .map(String::as_str)
+        .filter(|value| !value.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Step {} is missing its {} (argument {})", step.id, what, index + 1))
+}
@@ -0,0 +1,192 @@
+use super::{interpolation, WorkflowStep};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Runs a step once per item, with the item available as `$<as>`, e.g.
+/// `{"as": "file", "glob": "uploads/*.pdf"}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForEach {
+    #[serde(rename = "as", default = "default_loop_variable")]
+    pub variable: String,
+    #[serde(flatten)]
+    pub source: ForEachSource,
+}
+
+/// Where the items of a `for_each` loop come from. Sources are resolved when
+/// the step becomes ready, so they can use the outputs of its dependencies.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForEachSource {
+    /// A literal list; each item may reference variables.
+    Items(Vec<String>),
+    /// A variable holding a JSON array of strings or one item per line.
+    Variable(String),
+    /// Paths matching a glob pattern, in sorted order.
+    Glob(String),
+    /// The non-empty lines of a dependency's output.
+    Lines(String),
+}
+
+/// One expanded copy of a `for_each` or `matrix` step, with the variables it
+/// adds on top of the execution's.
+#[derive(Debug, Clone)]
+pub struct StepInstance {
+    pub step: WorkflowStep,
+    pub variables: HashMap<String, String>,
+}
+
+fn default_loop_variable() -> String {
+    "item".to_string()
+}
+
+impl WorkflowStep {
+    /// Whether the step expands into instances instead of running itself.
+    pub fn is_expanded(&self) -> bool {
+        self.for_each.is_some() || !self.matrix.is_empty()
+    }
+}
+
+/// Checks what can be checked before the workflow runs: loop variable names,
+/// that `lines` reads a dependency and that no matrix axis is empty.
+pub fn validate(step: &WorkflowStep) -> Result<()> {
+    if let Some(for_each) = &step.for_each {
+        if !interpolation::is_valid_name(&for_each.variable) {
+            return Err(anyhow::anyhow!("Invalid for_each variable name '{}'", for_each.variable));
+        }
+        if let ForEachSource::Lines(source) = &for_each.source {
+            if !step.depends_on.contains(source) {
+                return Err(anyhow::anyhow!(
+                    "for_each reads the output of {}, which must be listed in depends_on", source
+                ));
+            }
+        }
+    }
+    for (name, values) in &step.matrix {
+        if values.is_empty() {
+            return Err(anyhow::anyhow!("Matrix axis '{}' has no values", name));
+        }
+    }
+    if step.max_parallel == Some(0) {
+        return Err(anyhow::anyhow!("max_parallel must be at least 1"));
+    }
+    Ok(())
+}
+
+/// Rejects a step id of the form `<id>-<n>` next to an expanded step `<id>`.
+/// How many instances a step gets is only known when it runs, so every such
+/// id is reserved for its instances.
+pub fn check_reserved_id<'a>(step: &WorkflowStep, steps: impl IntoIterator<Item = &'a WorkflowStep>) -> Result<()> {
+    let owner = steps.into_iter().filter(|other| other.is_expanded()).find(|other| {
+        step.id
+            .strip_prefix(other.id.as_str())
+            .and_then(|rest| rest.strip_prefix('-'))
+            .is_some_and(|number| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+    });
+    match owner {
+        Some(owner) => Err(anyhow::anyhow!(
+            "step id '{}' is reserved for the instances of expanded step '{}'",
+            step.id,
+            owner.id
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Expands `step` into one instance per `for_each` item and matrix
+/// combination, with IDs `<id>-0`, `<id>-1`, ...
+pub fn expand(step: &WorkflowStep, variables: &HashMap<String, String>) -> Result<Vec<StepInstance>> {
+    let items = match &step.for_each {
+        Some(for_each) => resolve_items(&for_each.source, variables)?
+            .into_iter()
+            .map(|item| vec![(for_each.variable.clone(), item)])
+            .collect(),
+        None => vec![Vec::new()],
+    };
+
+    // Cartesian product of the matrix axes, in key order
+    let mut combinations: Vec<Vec<(String, String)>> = vec![Vec::new()];
+    for (name, values) in &step.matrix {
+        let mut expanded = Vec::with_capacity(combinations.len() * values.len());
+        for combination in &combinations {
+            for value in values {
+                let mut combination = combination.clone();
+                combination.push((name.clone(), interpolation::interpolate(value, variables)?));
+                expanded.push(combination);
+            }
+        }
+        combinations = expanded;
+    }
+
+    let mut instances = Vec::new();
+    for item in &items {
+        for combination in &combinations {
+            let bindings: Vec<&(String, String)> = item.iter().chain(combination).collect();
+            let label = bindings
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            let mut instance = step.clone();
+            instance.id = format!("{}-{}", step.id, instances.len());
+            instance.name = format!("{} [{}]", step.name, label);
+            instance.for_each = None;
+            instance.matrix = BTreeMap::new();
+            // The parent step publishes the combined output under `output`
+            instance.output = None;
+
+            instances.push(StepInstance {
+                step: instance,
+                variables: bindings.into_iter().cloned().collect(),
+            });
+        }
+    }
+
+    Ok(instances)
+}
+
+fn resolve_items(source: &ForEachSource, variables: &HashMap<String, String>) -> Result<Vec<String>> {
+    let items = match source {
+        ForEachSource::Items(items) => items
+            .iter()
+            .map(|item| interpolation::interpolate(item, variables))
+            .collect::<Result<Vec<_>>>()?,
+        ForEachSource::Variable(name) => {
+            let value = variables
+                .get(name)
+                .ok_or_else(|| anyhow::anyhow!("Undefined for_each variable '{}'", name))?;
+            match serde_json::from_str::<Vec<String>>(value) {
+                Ok(items) => items,
+                Err(_) => split_lines(value),
+            }
+        }
+        ForEachSource::Glob(pattern) => {
+            let pattern = interpolation::interpolate(pattern, variables)?;
+            let mut paths = Vec::new();
+            for path in glob::glob(&pattern)
+                .map_err(|e| anyhow::anyhow!("Invalid glob pattern '{}': {}", pattern, e))?
+            {
+                paths.push(path?.display().to_string());
+            }
+            paths.sort();
+            paths
+        }
+        ForEachSource::Lines(step_id) => {
+            let output = variables
+                .get(&format!("steps.{}.output", step_id))
+                .ok_or_else(|| anyhow::anyhow!("Step {} has no output to iterate over", step_id))?;
+            split_lines(output)
+        }
+    };
+    Ok(items)
+}
+
+fn split_lines(value: &str) -> Vec<String> {
+    value
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
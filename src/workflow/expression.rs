@@ -0,0 +1,529 @@
+use anyhow::Result;
+use regex::Regex;
+use std::{collections::HashMap, fmt};
+
+/// A parsed step condition, e.g.
+/// `$validation_result == 'success' && !contains(steps.scan.output, "virus")`.
+///
+/// Variables are written `$name`, `${name}` or as a bare dotted name such as
+/// `steps.build.status`. Undefined variables evaluate to `null` instead of
+/// failing, so `exists($name)` can be used to test for them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Variable(String),
+    Not(Box<Expr>),
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Call {
+        function: Function,
+        args: Vec<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    And,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Exists,
+    Contains,
+    Matches,
+    StartsWith,
+    EndsWith,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "exists" => Some(Function::Exists),
+            "contains" => Some(Function::Contains),
+            "matches" => Some(Function::Matches),
+            "starts_with" => Some(Function::StartsWith),
+            "ends_with" => Some(Function::EndsWith),
+            _ => None,
+        }
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Function::Exists => 1,
+            _ => 2,
+        }
+    }
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(value) => *value,
+            Value::Number(value) => *value != 0.0,
+            Value::String(value) => {
+                !value.is_empty() && !value.eq_ignore_ascii_case("false") && value != "0"
+            }
+        }
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            Value::String(value) => value.trim().parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, ""),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { source, tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if let Some((token, offset)) = parser.tokens.get(parser.pos) {
+            return Err(anyhow::anyhow!(
+                "Invalid condition '{}': unexpected {} at position {}",
+                source, token, offset
+            ));
+        }
+        Ok(expr)
+    }
+
+    pub fn evaluate(&self, variables: &HashMap<String, String>) -> Result<Value> {
+        match self {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Variable(name) => Ok(variables
+                .get(name)
+                .map(|value| Value::String(value.clone()))
+                .unwrap_or(Value::Null)),
+            Expr::Not(inner) => Ok(Value::Bool(!inner.evaluate(variables)?.is_truthy())),
+            Expr::Binary { op: BinaryOp::And, lhs, rhs } => Ok(Value::Bool(
+                lhs.evaluate(variables)?.is_truthy() && rhs.evaluate(variables)?.is_truthy(),
+            )),
+            Expr::Binary { op: BinaryOp::Or, lhs, rhs } => Ok(Value::Bool(
+                lhs.evaluate(variables)?.is_truthy() || rhs.evaluate(variables)?.is_truthy(),
+            )),
+            Expr::Binary { op, lhs, rhs } => {
+                let lhs = lhs.evaluate(variables)?;
+                let rhs = rhs.evaluate(variables)?;
+                Ok(Value::Bool(compare(*op, &lhs, &rhs)))
+            }
+            Expr::Call { function, args } => {
+                let values = args
+                    .iter()
+                    .map(|arg| arg.evaluate(variables))
+                    .collect::<Result<Vec<_>>>()?;
+                call(*function, &values)
+            }
+        }
+    }
+
+    /// Evaluates the expression and reduces the result to a boolean.
+    pub fn is_true(&self, variables: &HashMap<String, String>) -> Result<bool> {
+        Ok(self.evaluate(variables)?.is_truthy())
+    }
+
+    /// Names of every variable the expression reads.
+    pub fn variables(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.collect_variables(&mut names);
+        names
+    }
+
+    fn collect_variables(&self, names: &mut Vec<String>) {
+        match self {
+            Expr::Literal(_) => {}
+            Expr::Variable(name) => names.push(name.clone()),
+            Expr::Not(inner) => inner.collect_variables(names),
+            Expr::Binary { lhs, rhs, .. } => {
+                lhs.collect_variables(names);
+                rhs.collect_variables(names);
+            }
+            Expr::Call { args, .. } => args.iter().for_each(|arg| arg.collect_variables(names)),
+        }
+    }
+}
+
+fn compare(op: BinaryOp, lhs: &Value, rhs: &Value) -> bool {
+    if matches!(lhs, Value::Null) || matches!(rhs, Value::Null) {
+        let both_null = matches!(lhs, Value::Null) && matches!(rhs, Value::Null);
+        return match op {
+            BinaryOp::Eq => both_null,
+            BinaryOp::Ne => !both_null,
+            _ => false,
+        };
+    }
+
+    // Variables are strings, so compare numerically whenever a number is involved
+    // and both sides parse, and against the literal's text otherwise
+    let ordering = match (lhs, rhs) {
+        (Value::Number(_), _) | (_, Value::Number(_)) => match (lhs.as_number(), rhs.as_number()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => lhs.to_string().partial_cmp(&rhs.to_string()),
+        },
+        (Value::Bool(a), _) => Some(a.to_string().cmp(&rhs.to_string().to_lowercase())),
+        (_, Value::Bool(b)) => Some(lhs.to_string().to_lowercase().cmp(&b.to_string())),
+        _ => lhs.to_string().partial_cmp(&rhs.to_string()),
+    };
+
+    match (op, ordering) {
+        (BinaryOp::Eq, ordering) => ordering == Some(std::cmp::Ordering::Equal),
+        (BinaryOp::Ne, ordering) => ordering != Some(std::cmp::Ordering::Equal),
+        (_, None) => false,
+        (BinaryOp::Lt, Some(ordering)) => ordering.is_lt(),
+        (BinaryOp::Le, Some(ordering)) => ordering.is_le(),
+        (BinaryOp::Gt, Some(ordering)) => ordering.is_gt(),
+        (BinaryOp::Ge, Some(ordering)) => ordering.is_ge(),
+        (BinaryOp::And | BinaryOp::Or, _) => unreachable!("logical operators short-circuit"),
+    }
+}
+
+fn call(function: Function, args: &[Value]) -> Result<Value> {
+    let text = |index: usize| args[index].to_string();
+    let result = match function {
+        Function::Exists => !matches!(args[0], Value::Null),
+        Function::Contains => text(0).contains(&text(1)),
+        Function::StartsWith => text(0).starts_with(&text(1)),
+        Function::EndsWith => text(0).ends_with(&text(1)),
+        Function::Matches => Regex::new(&text(1))
+            .map_err(|e| anyhow::anyhow!("Invalid pattern '{}' in matches(): {}", text(1), e))?
+            .is_match(&text(0)),
+    };
+    Ok(Value::Bool(result))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    String(String),
+    Number(f64),
+    Variable(String),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::String(value) => write!(f, "string '{}'", value),
+            Token::Number(value) => write!(f, "number {}", value),
+            Token::Variable(name) => write!(f, "variable '${}'", name),
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Op(op) => write!(f, "'{}'", op),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+        }
+    }
+}
+
+const OPERATORS: [&str; 9] = ["==", "!=", "<=", ">=", "&&", "||", "<", ">", "!"];
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let error = |message: String, at: usize| {
+        anyhow::anyhow!("Invalid condition '{}': {} at position {}", source, message, at)
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let token = match c {
+            '(' => {
+                i += 1;
+                Token::LParen
+            }
+            ')' => {
+                i += 1;
+                Token::RParen
+            }
+            ',' => {
+                i += 1;
+                Token::Comma
+            }
+            '\'' | '"' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(error("unterminated string".to_string(), start)),
+                        Some('\\') if i + 1 < chars.len() => {
+                            value.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(&quote) if quote == c => {
+                            i += 1;
+                            break;
+                        }
+                        Some(&other) => {
+                            value.push(other);
+                            i += 1;
+                        }
+                    }
+                }
+                Token::String(value)
+            }
+            '$' => {
+                let braced = chars.get(i + 1) == Some(&'{');
+                i += if braced { 2 } else { 1 };
+                let name_start = i;
+                while i < chars.len() && is_name_char(chars[i], braced) {
+                    i += 1;
+                }
+                let name: String = chars[name_start..i].iter().collect();
+                let name = name.trim_end_matches('.').to_string();
+                i = name_start + name.chars().count();
+                if name.is_empty() {
+                    return Err(error("expected variable name after '$'".to_string(), start));
+                }
+                if braced {
+                    if chars.get(i) != Some(&'}') {
+                        return Err(error("expected '}'".to_string(), i));
+                    }
+                    i += 1;
+                }
+                Token::Variable(name)
+            }
+            c if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) => {
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let value = text
+                    .parse()
+                    .map_err(|_| error(format!("invalid number '{}'", text), start))?;
+                Token::Number(value)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                while i < chars.len() && is_name_char(chars[i], false) {
+                    i += 1;
+                }
+                let name: String = chars[start..i].iter().collect();
+                match name.as_str() {
+                    "and" => Token::Op("&&"),
+                    "or" => Token::Op("||"),
+                    "not" => Token::Op("!"),
+                    _ => Token::Ident(name),
+                }
+            }
+            _ => {
+                let rest: String = chars[i..].iter().take(2).collect();
+                let op = OPERATORS
+                    .iter()
+                    .find(|op| rest.starts_with(*op))
+                    .ok_or_else(|| error(format!("unexpected character '{}'", c), start))?;
+                i += op.len();
+                Token::Op(op)
+            }
+        };
+
+        tokens.push((token, start));
+    }
+
+    Ok(tokens)
+}
+
+fn is_name_char(c: char, braced: bool) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || (braced && c == '-')
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .map(|(token, _)| token.clone())
+            .ok_or_else(|| {
+                anyhow::anyhow!("Invalid condition '{}': unexpected end of expression", self.source)
+            })?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        match self.tokens.get(self.pos) {
+            Some((token, offset)) => anyhow::anyhow!(
+                "Invalid condition '{}': {}, found {} at position {}",
+                self.source, message, token, offset
+            ),
+            None => anyhow::anyhow!(
+                "Invalid condition '{}': {}, found end of expression",
+                self.source, message
+            ),
+        }
+    }
+
+    fn eat_op(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_and()?;
+        while self.eat_op(&["||"]).is_some() {
+            let rhs = self.parse_and()?;
+            lhs = Expr::Binary { op: BinaryOp::Or, lhs: Box::new(lhs), rhs: Box::new(rhs) };
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_comparison()?;
+        while self.eat_op(&["&&"]).is_some() {
+            let rhs = self.parse_comparison()?;
+            lhs = Expr::Binary { op: BinaryOp::And, lhs: Box::new(lhs), rhs: Box::new(rhs) };
+        }
+        Ok(lhs)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr> {
+        let lhs = self.parse_unary()?;
+        let op = match self.eat_op(&["==", "!=", "<=", ">=", "<", ">"]) {
+            Some("==") => BinaryOp::Eq,
+            Some("!=") => BinaryOp::Ne,
+            Some("<=") => BinaryOp::Le,
+            Some(">=") => BinaryOp::Ge,
+            Some("<") => BinaryOp::Lt,
+            Some(">") => BinaryOp::Gt,
+            _ => return Ok(lhs),
+        };
+        let rhs = self.parse_unary()?;
+        Ok(Expr::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs) })
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        if self.eat_op(&["!"]).is_some() {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        if self.peek().is_none() {
+            return Err(self.error("expected a value"));
+        }
+        match self.next()? {
+            Token::String(value) => Ok(Expr::Literal(Value::String(value))),
+            Token::Number(value) => Ok(Expr::Literal(Value::Number(value))),
+            Token::Variable(name) => Ok(Expr::Variable(name)),
+            Token::LParen => {
+                let expr = self.parse_or()?;
+                match self.peek() {
+                    Some(Token::RParen) => {
+                        self.pos += 1;
+                        Ok(expr)
+                    }
+                    _ => Err(self.error("expected ')'")),
+                }
+            }
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ if self.peek() == Some(&Token::LParen) => self.parse_call(&name),
+                _ => Ok(Expr::Variable(name)),
+            },
+            token => {
+                self.pos -= 1;
+                Err(self.error(&format!("expected a value instead of {}", token)))
+            }
+        }
+    }
+
+    fn parse_call(&mut self, name: &str) -> Result<Expr> {
+        let function = Function::from_name(name).ok_or_else(|| {
+            anyhow::anyhow!("Invalid condition '{}': unknown function '{}'", self.source, name)
+        })?;
+        self.pos += 1; // the opening parenthesis
+
+        let mut args = Vec::new();
+        if self.peek() == Some(&Token::RParen) {
+            self.pos += 1;
+        } else {
+            loop {
+                args.push(self.parse_or()?);
+                match self.next()? {
+                    Token::Comma => continue,
+                    Token::RParen => break,
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("expected ',' or ')'"));
+                    }
+                }
+            }
+        }
+
+        if args.len() != function.arity() {
+            return Err(anyhow::anyhow!(
+                "Invalid condition '{}': {}() takes {} argument(s), got {}",
+                self.source, name, function.arity(), args.len()
+            ));
+        }
+
+        // Catch bad literal patterns now rather than when the step runs
+        if let (Function::Matches, Some(Expr::Literal(Value::String(pattern)))) = (function, args.get(1)) {
+            Regex::new(pattern).map_err(|e| {
+                anyhow::anyhow!("Invalid condition '{}': bad pattern '{}': {}", self.source, pattern, e)
+            })?;
+        }
+
+        Ok(Expr::Call { function, args })
+    }
+}
@@ -0,0 +1,84 @@
+use super::{templates, Workflow};
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt, fs, path::Path};
+
+/// File formats a workflow can be written in, chosen by file extension. All
+/// three deserialize into the same `Workflow`, so they behave identically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkflowFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl WorkflowFormat {
+    /// The format of `path`, or `None` for an unsupported extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+
+    /// Like `from_path`, failing for unsupported extensions.
+    pub fn of(path: &Path) -> Result<Self> {
+        Self::from_path(path).ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} is not a workflow file; expected a .json, .yaml, .yml or .toml extension",
+                path.display()
+            )
+        })
+    }
+
+    /// Parses `content`. Errors carry the line and column of the problem.
+    pub fn parse<T: DeserializeOwned>(self, content: &str) -> Result<T> {
+        Ok(match self {
+            Self::Json => serde_json::from_str(content)?,
+            Self::Yaml => serde_yaml::from_str(content)?,
+            Self::Toml => toml::from_str(content)?,
+        })
+    }
+
+    pub fn render<T: Serialize>(self, value: &T) -> Result<String> {
+        Ok(match self {
+            Self::Json => serde_json::to_string_pretty(value)? + "\n",
+            Self::Yaml => serde_yaml::to_string(value)?,
+            Self::Toml => toml::to_string_pretty(value)?,
+        })
+    }
+}
+
+impl fmt::Display for WorkflowFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Json => "JSON",
+            Self::Yaml => "YAML",
+            Self::Toml => "TOML",
+        })
+    }
+}
+
+/// Reads a workflow file in whichever format its extension names, resolving
+/// its `include`s and step templates.
+pub fn read_workflow(path: &Path) -> Result<Workflow> {
+    let format = WorkflowFormat::of(path)?;
+    let content = fs::read_to_string(path)?;
+    let invalid = |e| anyhow::anyhow!("Invalid {} workflow {}: {}", format, path.display(), e);
+    let document: serde_json::Value = format.parse(&content).map_err(invalid)?;
+    if !templates::is_composed(&document) {
+        // Parse the text itself so errors keep their line and column
+        return format.parse(&content).map_err(invalid);
+    }
+    serde_json::from_value(templates::resolve(document, path).map_err(invalid)?).map_err(|e| invalid(e.into()))
+}
+
+/// Rewrites the workflow at `input` in the format of `output`'s extension. The
+/// result is the resolved workflow, with includes and templates expanded.
+pub fn convert(input: &Path, output: &Path) -> Result<()> {
+    let workflow = read_workflow(input)?;
+    fs::write(output, WorkflowFormat::of(output)?.render(&workflow)?)?;
+    Ok(())
+}
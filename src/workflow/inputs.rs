@@ -0,0 +1,185 @@
+use super::Workflow;
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path};
+
+/// A value the workflow expects from whoever runs it, declared under `inputs`
+/// and available to steps as a variable of the same name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputSpec {
+    #[serde(rename = "type", default)]
+    pub input_type: InputType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<serde_json::Value>,
+    /// Regex the whole value must match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum InputType {
+    #[default]
+    String,
+    Integer,
+    Number,
+    Boolean,
+    /// Path to an existing file.
+    File,
+    /// Path to an existing directory.
+    Directory,
+}
+
+impl InputSpec {
+    /// Checks `value` against the declared type and pattern, returning it in
+    /// normalized form (booleans become `true`/`false`).
+    pub fn check(&self, value: &str) -> Result<String> {
+        let value = match self.input_type {
+            InputType::String => value.to_string(),
+            InputType::Integer => value
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| anyhow::anyhow!("'{}' is not an integer", value))?
+                .to_string(),
+            InputType::Number => {
+                value
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|_| anyhow::anyhow!("'{}' is not a number", value))?;
+                value.trim().to_string()
+            }
+            InputType::Boolean => match value.trim().to_lowercase().as_str() {
+                "true" | "yes" | "1" => "true".to_string(),
+                "false" | "no" | "0" => "false".to_string(),
+                _ => return Err(anyhow::anyhow!("'{}' is not a boolean", value)),
+            },
+            InputType::File => {
+                if !Path::new(value).is_file() {
+                    return Err(anyhow::anyhow!("file '{}' does not exist", value));
+                }
+                value.to_string()
+            }
+            InputType::Directory => {
+                if !Path::new(value).is_dir() {
+                    return Err(anyhow::anyhow!("directory '{}' does not exist", value));
+                }
+                value.to_string()
+            }
+        };
+
+        if let Some(pattern) = &self.pattern {
+            let regex = Regex::new(&format!("^(?:{})$", pattern))?;
+            if !regex.is_match(&value) {
+                return Err(anyhow::anyhow!("'{}' does not match pattern '{}'", value, pattern));
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// Checks the declarations themselves when the workflow is loaded.
+pub fn validate_declarations(workflow: &Workflow) -> Result<()> {
+    for (name, spec) in &workflow.inputs {
+        if let Some(pattern) = &spec.pattern {
+            Regex::new(pattern).map_err(|e| anyhow::anyhow!("Input {} has an invalid pattern: {}", name, e))?;
+        }
+        if let Some(default) = spec.default.as_ref().and_then(json_to_string) {
+            // Paths may only need to exist when the workflow runs
+            if !matches!(spec.input_type, InputType::File | InputType::Directory) {
+                spec.check(&default)
+                    .map_err(|e| anyhow::anyhow!("Input {} has an invalid default: {}", name, e))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The variables a run starts with: the workflow's `variables`, overlaid with
+/// every declared input, taken from `provided` or else its default. All problems
+/// (missing required inputs, invalid values, unknown names) are reported at once.
+///
+/// With `allow_undeclared`, as for the `inputs` of a `Workflow` step, names that
+/// are neither inputs nor variables are not rejected but become variables too.
+pub fn resolve(
+    workflow: &Workflow,
+    provided: &HashMap<String, String>,
+    allow_undeclared: bool,
+) -> Result<HashMap<String, String>> {
+    let mut variables = workflow.variables.clone();
+    let mut problems = Vec::new();
+
+    if !allow_undeclared {
+        let mut unknown: Vec<&String> = provided
+            .keys()
+            .filter(|name| !workflow.inputs.contains_key(*name) && !workflow.variables.contains_key(*name))
+            .collect();
+        unknown.sort();
+        for name in unknown {
+            problems.push(format!("{} is not an input of this workflow", name));
+        }
+    }
+
+    for (name, spec) in &workflow.inputs {
+        let value = provided
+            .get(name)
+            .cloned()
+            .or_else(|| spec.default.as_ref().and_then(json_to_string));
+        match value {
+            Some(value) => match spec.check(&value) {
+                Ok(value) => {
+                    variables.insert(name.clone(), value);
+                }
+                Err(e) => problems.push(format!("{}: {}", name, e)),
+            },
+            None if spec.required => problems.push(format!("{} is required", name)),
+            None => {}
+        }
+    }
+    for (name, value) in provided {
+        if !workflow.inputs.contains_key(name) {
+            variables.insert(name.clone(), value.clone());
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Invalid inputs for workflow {}:\n  - {}",
+            workflow.name,
+            problems.join("\n  - ")
+        ));
+    }
+    Ok(variables)
+}
+
+/// Reads input values from a JSON object file, as passed with `--inputs`.
+/// Arrays and objects are kept as JSON text, e.g. for a `for_each` variable.
+pub fn load_inputs_file(path: &Path) -> Result<HashMap<String, String>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Cannot read inputs file {}: {}", path.display(), e))?;
+    let values: HashMap<String, serde_json::Value> = serde_json::from_str(&content)
+        .map_err(|e| anyhow::anyhow!("Inputs file {} must be a JSON object: {}", path.display(), e))?;
+    Ok(values
+        .into_iter()
+        .filter_map(|(name, value)| json_to_string(&value).map(|value| (name, value)))
+        .collect())
+}
+
+/// Parses a `key=value` assignment as passed with `--set`.
+pub fn parse_assignment(assignment: &str) -> Result<(String, String)> {
+    match assignment.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.to_string())),
+        _ => Err(anyhow::anyhow!("Expected KEY=VALUE, got '{}'", assignment)),
+    }
+}
+
+fn json_to_string(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(value) => Some(value.clone()),
+        other => Some(other.to_string()),
+    }
+}
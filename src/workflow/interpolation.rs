@@ -0,0 +1,204 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+/// A piece of a parsed template string.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Literal(String),
+    Variable {
+        name: String,
+        default: Option<String>,
+    },
+}
+
+/// A field that is either a literal value or a template resolving to one,
+/// e.g. `"timeout": 30` or `"timeout": "${step_timeout:-30}"`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Templated<T> {
+    Value(T),
+    Template(String),
+}
+
+impl<T> Templated<T>
+where
+    T: Clone + FromStr,
+    T::Err: Display,
+{
+    pub fn resolve(&self, variables: &HashMap<String, String>) -> Result<T> {
+        match self {
+            Templated::Value(value) => Ok(value.clone()),
+            Templated::Template(template) => {
+                let rendered = interpolate(template, variables)?;
+                rendered.trim().parse::<T>().map_err(|e| {
+                    anyhow::anyhow!("Invalid value '{}' from template '{}': {}", rendered, template, e)
+                })
+            }
+        }
+    }
+}
+
+/// Splits a template into literal text and variable references.
+///
+/// Supported forms are `$name`, `${name}` and `${name:-default}`; `$$` is a
+/// literal dollar sign. Braced names may contain dots; unbraced ones only when
+/// they refer to a step result (`$steps.build.output`), so `$file.gz` is `$file`
+/// followed by `.gz`.
+pub fn parse(template: &str) -> Result<Vec<Segment>> {
+    parse_with(template, |_| false)
+}
+
+/// Like `parse`, but an unbraced name also takes a `.part` when `takes_dots`
+/// holds for the name read so far, e.g. for a step `output` name whose
+/// `.status` and `.exit_code` are published alongside it.
+pub fn parse_with(template: &str, takes_dots: impl Fn(&str) -> bool) -> Result<Vec<Segment>> {
+    let chars: Vec<char> = template.chars().collect();
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] != '$' {
+            literal.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        match chars.get(i + 1) {
+            Some('$') => {
+                literal.push('$');
+                i += 2;
+            }
+            Some('{') => {
+                let close = chars[i + 2..]
+                    .iter()
+                    .position(|&c| c == '}')
+                    .map(|offset| i + 2 + offset)
+                    .ok_or_else(|| anyhow::anyhow!("Unterminated '${{' in '{}'", template))?;
+                let body: String = chars[i + 2..close].iter().collect();
+                let (name, default) = match body.split_once(":-") {
+                    Some((name, default)) => (name.to_string(), Some(default.to_string())),
+                    None => (body, None),
+                };
+                if !is_valid_name(&name) {
+                    return Err(anyhow::anyhow!("Invalid variable name '{}' in '{}'", name, template));
+                }
+                flush_literal(&mut literal, &mut segments);
+                segments.push(Segment::Variable { name, default });
+                i = close + 1;
+            }
+            Some(&c) if is_name_start(c) => {
+                let start = i + 1;
+                let name_end = |mut end: usize| {
+                    while chars.get(end).is_some_and(|&c| is_name_char(c)) {
+                        end += 1;
+                    }
+                    end
+                };
+                let mut end = name_end(start);
+                let mut name: String = chars[start..end].iter().collect();
+                while chars.get(end) == Some(&'.')
+                    && chars.get(end + 1).is_some_and(|&c| is_name_char(c))
+                    && (name == "steps" || name.starts_with("steps.") || takes_dots(&name))
+                {
+                    end = name_end(end + 1);
+                    name = chars[start..end].iter().collect();
+                }
+                flush_literal(&mut literal, &mut segments);
+                segments.push(Segment::Variable { name, default: None });
+                i = end;
+            }
+            // A lone `$` (e.g. "costs $5") is kept as text
+            _ => {
+                literal.push('$');
+                i += 1;
+            }
+        }
+    }
+
+    flush_literal(&mut literal, &mut segments);
+    Ok(segments)
+}
+
+/// Substitutes variable references in `template`, failing on any variable
+/// that is neither defined nor given a default.
+pub fn interpolate(template: &str, variables: &HashMap<String, String>) -> Result<String> {
+    interpolate_with(template, variables, str::to_string)
+}
+
+/// Like `interpolate`, but passes every substituted value through `escape`,
+/// e.g. `shell_quote` when the result becomes part of a shell script. An
+/// unbraced name takes a `.part` when some variable is named that way, so
+/// `$report.status` reads the status published for a step `output` named `report`.
+pub fn interpolate_with(
+    template: &str,
+    variables: &HashMap<String, String>,
+    escape: impl Fn(&str) -> String,
+) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let takes_dots = |name: &str| {
+        let prefix = format!("{}.", name);
+        variables.keys().any(|key| key.starts_with(&prefix))
+    };
+
+    for segment in parse_with(template, takes_dots)? {
+        match segment {
+            Segment::Literal(text) => rendered.push_str(&text),
+            Segment::Variable { name, default } => match (variables.get(&name), default) {
+                (Some(value), _) => rendered.push_str(&escape(value)),
+                (None, Some(default)) => rendered.push_str(&escape(&default)),
+                (None, None) => {
+                    return Err(anyhow::anyhow!("Undefined variable '{}' in '{}'", name, template));
+                }
+            },
+        }
+    }
+
+    Ok(rendered)
+}
+
+/// Names of all variables referenced by `template` without a default.
+pub fn required_variables(template: &str) -> Result<Vec<String>> {
+    Ok(parse(template)?
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Variable { name, default: None } => Some(name),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Quotes `value` as a single `/bin/sh` word.
+pub fn shell_quote(value: &str) -> String {
+    let is_plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c));
+    if is_plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+fn flush_literal(literal: &mut String, segments: &mut Vec<Segment>) {
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(std::mem::take(literal)));
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Braced references also allow `-`, so `${steps.fetch-data.output}` works.
+pub(super) fn is_valid_name(name: &str) -> bool {
+    name.split('.')
+        .all(|part| !part.is_empty() && part.chars().all(|c| is_name_char(c) || c == '-'))
+        && name.chars().next().is_some_and(is_name_start)
+}
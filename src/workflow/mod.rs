@@ -1,19 +1,68 @@
-use crate::config::WorkflowConfig;
+pub mod approval;
+pub mod artifacts;
+pub mod cache;
+mod executor;
+pub mod expansion;
+pub mod expression;
+pub mod format;
+pub mod inputs;
+pub mod interpolation;
+pub mod plan;
+pub mod process;
+pub mod registry;
+pub mod retry;
+pub mod secrets;
+pub mod sensor;
+mod subworkflow;
+pub mod templates;
+pub mod validation;
+
+use crate::{config::WorkflowConfig, upload::UploadManager};
 use anyhow::Result;
 use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use futures::stream::{FuturesUnordered, StreamExt};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     fs,
     path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
     process::Command,
+    sync::{watch, Semaphore},
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
+pub use expansion::{ForEach, ForEachSource};
+pub use expression::Expr;
+pub use inputs::{InputSpec, InputType};
+pub use interpolation::Templated;
+pub use approval::{ApprovalDecision, ApprovalRecord, ApprovalRequest, ApprovalSpec};
+pub use artifacts::Artifact;
+pub use cache::{CacheSpec, StepCache};
+pub use format::WorkflowFormat;
+pub use plan::{ExecutionPlan, PlanDecision, PlannedStep};
+pub use registry::{RegisteredWorkflow, WorkflowRegistry};
+pub use validation::ValidationIssue;
+pub use crate::utils::retry::{Backoff, RetryPolicy, StderrPattern};
+pub use retry::StepAttempt;
+pub use secrets::{SecretSource, Secrets};
+pub use sensor::WaitSpec;
+
 #[derive(Debug)]
 pub struct WorkflowEngine {
     config: WorkflowConfig,
+    step_slots: Arc<Semaphore>,
+    upload_manager: Option<UploadManager>,
+    cache: Option<StepCache>,
+    active_executions: DashMap<Uuid, watch::Sender<bool>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,7 +74,26 @@ pub struct Workflow {
     pub created_at: DateTime<Utc>,
     pub steps: Vec<WorkflowStep>,
     pub variables: HashMap<String, String>,
+    /// Values supplied by whoever runs the workflow, see `InputSpec`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub inputs: BTreeMap<String, InputSpec>,
+    /// Values steps use like variables but that are masked as `***` in outputs,
+    /// logs and execution records, see `SecretSource`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub secrets: BTreeMap<String, SecretSource>,
     pub metadata: WorkflowMetadata,
+    /// Maximum number of this workflow's steps running at once. Unset means
+    /// only the engine-wide `max_parallel_steps` limit applies.
+    #[serde(default)]
+    pub max_parallel_steps: Option<usize>,
+    /// Steps that run once all other steps have finished, whether they succeeded,
+    /// failed or were cancelled. They may only depend on each other.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub finally: Vec<WorkflowStep>,
+    /// Deadline for the whole run, overriding `WorkflowConfig.workflow_timeout_seconds`.
+    /// Steps still running when it passes are cancelled and the run ends as `TimedOut`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_seconds: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,13 +101,107 @@ pub struct WorkflowStep {
     pub id: String,
     pub name: String,
     pub step_type: StepType,
+    #[serde(default)]
     pub command: String,
     pub args: Vec<String>,
-    pub timeout: Option<u64>,
+    pub timeout: Option<Templated<u64>>,
     pub retry_count: Option<u32>,
     pub depends_on: Vec<String>,
     pub condition: Option<String>,
     pub output: Option<String>,
+    /// Run `command` and `args` as one `/bin/sh -c` script. Interpolated
+    /// variable values are shell-quoted; the literal text is not.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shell: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    /// Extra environment variables for the step's process.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Text written to the process's standard input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<String>,
+    #[serde(default, skip_serializing_if = "InheritEnv::is_all")]
+    pub inherit_env: InheritEnv,
+    /// Overrides `WorkflowConfig.retry_policy` for this step.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_policy: Option<RetryPolicy>,
+    /// Let the workflow carry on when this step fails; it then ends as
+    /// `CompletedWithErrors` rather than `Failed`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub continue_on_error: bool,
+    #[serde(default, skip_serializing_if = "RunCondition::is_on_success")]
+    pub run_if: RunCondition,
+    /// Run the step once per item, see `ForEach`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub for_each: Option<ForEach>,
+    /// Run the step once per combination of these values, each available as a variable.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub matrix: BTreeMap<String, Vec<String>>,
+    /// Maximum number of the step's `for_each`/`matrix` instances running at once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_parallel: Option<usize>,
+    /// For `Workflow` steps: child workflow variables, set from these values.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub inputs: BTreeMap<String, String>,
+    /// For `Workflow` steps: child variables to publish, keyed by the name they get
+    /// under the step, e.g. `{"archive": "steps.compress.output"}` gives `steps.<id>.archive`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub outputs: BTreeMap<String, String>,
+    /// Files (paths or globs, relative to `working_dir`) kept as artifacts of
+    /// the execution once the step succeeds, see `Artifact`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<String>,
+    /// Reuse the step's earlier result when nothing it depends on changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheSpec>,
+    /// For `Approval` steps: who may decide and what happens on timeout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval: Option<ApprovalSpec>,
+    /// For `Wait` steps: how often to poll.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wait: Option<WaitSpec>,
+}
+
+/// When a step runs, judged by the steps it depends on. A dependency that
+/// failed, or was skipped because of an earlier failure, counts as failed; one
+/// that failed with `continue_on_error` does not.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum RunCondition {
+    /// Only when no dependency failed and no other step failed the workflow.
+    #[default]
+    OnSuccess,
+    /// Only when a dependency failed, e.g. to report or roll back.
+    OnFailure,
+    /// Whatever the outcome of its dependencies.
+    Always,
+}
+
+impl RunCondition {
+    fn is_on_success(&self) -> bool {
+        *self == RunCondition::OnSuccess
+    }
+}
+
+/// Which of the orchestrator's environment variables a step process sees:
+/// `true` (everything, the default), `false` (nothing) or a list of names.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InheritEnv {
+    All(bool),
+    Only(Vec<String>),
+}
+
+impl Default for InheritEnv {
+    fn default() -> Self {
+        InheritEnv::All(true)
+    }
+}
+
+impl InheritEnv {
+    fn is_all(&self) -> bool {
+        *self == InheritEnv::All(true)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,6 +213,20 @@ pub enum StepType {
     Transform,
     Validate,
     Notify,
+    /// Runs another workflow file from `workflow_dir` as a child execution.
+    Workflow,
+    /// Waits for someone to approve or reject it, see `ApprovalSpec`.
+    Approval,
+    /// Polls until a condition holds, such as a file appearing, see `WaitSpec`.
+    Wait,
+}
+
+impl StepType {
+    /// `Wait` and `Approval` steps spend their time waiting rather than working,
+    /// so they hold no step slot and do not count towards `max_parallel_steps`.
+    pub fn waits(&self) -> bool {
+        matches!(self, StepType::Wait | StepType::Approval)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -87,6 +263,33 @@ pub struct WorkflowExecution {
     pub steps_executed: Vec<StepExecution>,
     pub variables: HashMap<String, String>,
     pub error_message: Option<String>,
+    /// Workflow file the execution was started from, used to resume it.
+    #[serde(default)]
+    pub workflow_path: Option<String>,
+    /// Hash of the step definitions that ran, see `Workflow::definition_hash`.
+    #[serde(default)]
+    pub definition_hash: Option<String>,
+    /// `Workflow::version` of the workflow that ran.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workflow_version: Option<String>,
+    /// Hash of the whole workflow that ran, see `Workflow::content_hash`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    #[serde(default)]
+    pub resume_count: u32,
+    /// When the current run must finish; every start or resume gets a full budget.
+    #[serde(default)]
+    pub deadline: Option<DateTime<Utc>>,
+    /// The execution whose `Workflow` step started this one.
+    #[serde(default)]
+    pub parent_execution_id: Option<Uuid>,
+    /// When a run passed the workflow's `metadata.estimated_duration`, measured
+    /// from the start of that run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimate_exceeded_at: Option<DateTime<Utc>>,
+    /// Resolved when the run starts or resumes; never written to the record.
+    #[serde(skip)]
+    pub secrets: Secrets,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -98,6 +301,33 @@ pub struct StepExecution {
     pub output: Option<String>,
     pub error_message: Option<String>,
     pub retry_count: u32,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// Why the step's processes were killed, e.g. after exceeding its timeout.
+    #[serde(default)]
+    pub termination_reason: Option<String>,
+    /// Extra values produced by built-in step types, published as `steps.<id>.<key>`.
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    #[serde(default)]
+    pub attempts: Vec<StepAttempt>,
+    /// Why a `Skipped` step did not run.
+    #[serde(default)]
+    pub skip_reason: Option<String>,
+    /// The `for_each`/`matrix` step this execution is an instance of.
+    #[serde(default)]
+    pub instance_of: Option<String>,
+    /// The execution started by a `Workflow` step.
+    #[serde(default)]
+    pub child_execution_id: Option<Uuid>,
+    #[serde(default)]
+    pub artifacts: Vec<Artifact>,
+    /// Key of the cache entry the step was restored from or stored as.
+    #[serde(default)]
+    pub cache_key: Option<String>,
+    /// How an `Approval` step was decided, and by whom.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval: Option<ApprovalRecord>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -105,46 +335,443 @@ pub enum ExecutionStatus {
     Pending,
     Running,
     Completed,
+    /// Completed, but at least one step failed with `continue_on_error`.
+    CompletedWithErrors,
     Failed,
     Cancelled,
     Skipped,
+    TimedOut,
+    /// Not run; the result was restored from the step cache.
+    Cached,
+    /// An `Approval` step is waiting for someone to approve or reject it.
+    WaitingForApproval,
+}
+
+impl StepExecution {
+    /// Whether the step needs no further work when its execution is resumed.
+    pub fn is_done(&self) -> bool {
+        matches!(
+            self.status,
+            ExecutionStatus::Completed | ExecutionStatus::Skipped | ExecutionStatus::Cached
+        )
+    }
+
+    fn running(step_id: &str) -> Self {
+        Self {
+            step_id: step_id.to_string(),
+            status: ExecutionStatus::Running,
+            started_at: Utc::now(),
+            completed_at: None,
+            output: None,
+            error_message: None,
+            retry_count: 0,
+            exit_code: None,
+            termination_reason: None,
+            variables: HashMap::new(),
+            attempts: Vec::new(),
+            skip_reason: None,
+            instance_of: None,
+            child_execution_id: None,
+            artifacts: Vec::new(),
+            cache_key: None,
+            approval: None,
+        }
+    }
+
+    /// A step that has started: `Running`, or `WaitingForApproval` for an approval gate.
+    fn started(step: &WorkflowStep) -> Self {
+        let mut step_execution = Self::running(&step.id);
+        if matches!(step.step_type, StepType::Approval) {
+            step_execution.status = ExecutionStatus::WaitingForApproval;
+        }
+        step_execution
+    }
+
+    fn skipped(step_id: &str, reason: &str) -> Self {
+        Self {
+            status: ExecutionStatus::Skipped,
+            completed_at: Some(Utc::now()),
+            skip_reason: Some(reason.to_string()),
+            ..Self::running(step_id)
+        }
+    }
+}
+
+impl ExecutionStatus {
+    /// Whether a step or execution with this status has started but not finished.
+    pub fn is_in_progress(&self) -> bool {
+        matches!(self, Self::Running | Self::WaitingForApproval)
+    }
+}
+
+impl std::fmt::Display for ExecutionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Captured result of a finished step, either from a process or a built-in step type.
+#[derive(Debug, Clone)]
+pub struct StepOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: Option<i32>,
+    pub variables: HashMap<String, String>,
+    pub child_execution_id: Option<Uuid>,
+    pub approval: Option<ApprovalRecord>,
+}
+
+impl StepOutput {
+    fn builtin(stdout: String, variables: HashMap<String, String>) -> Self {
+        Self {
+            stdout,
+            stderr: String::new(),
+            exit_code: Some(0),
+            variables,
+            child_execution_id: None,
+            approval: None,
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Keeps a run registered in `active_executions` while its future is alive. A
+/// future dropped mid-run, such as a sub-workflow whose parent step timed out,
+/// also gets its last checkpoint finalized as `Cancelled`.
+struct ActiveRun<'a> {
+    engine: &'a WorkflowEngine,
+    execution_id: Uuid,
+    overrun_warning: Option<tokio::task::JoinHandle<()>>,
+    finished: bool,
+}
+
+impl Drop for ActiveRun<'_> {
+    fn drop(&mut self) {
+        self.engine.active_executions.remove(&self.execution_id);
+        if let Some(overrun_warning) = &self.overrun_warning {
+            overrun_warning.abort();
+        }
+        if !self.finished {
+            self.engine.finalize_interrupted(self.execution_id);
+        }
+    }
+}
+
+/// The instances of a `for_each` or `matrix` step while they run.
+struct InstanceGroup<'a> {
+    step: &'a WorkflowStep,
+    record: StepExecution,
+    order: Vec<String>,
+    queued: VecDeque<expansion::StepInstance>,
+    running: usize,
+    failed: bool,
+    finished: HashMap<String, StepExecution>,
+}
+
+/// How one batch of steps (the main steps or the `finally` block) ended.
+#[derive(Default)]
+struct StepsOutcome {
+    cancelled: bool,
+    deadline_exceeded: bool,
+    /// The first step that failed without `continue_on_error`.
+    failed_step: Option<StepExecution>,
+    tolerated_failures: usize,
+}
+
+impl WorkflowExecution {
+    /// The variables steps see: the execution's variables plus its secrets.
+    fn step_variables(&self) -> Cow<'_, HashMap<String, String>> {
+        if self.secrets.is_empty() {
+            return Cow::Borrowed(&self.variables);
+        }
+        let mut variables = self.variables.clone();
+        variables.extend(self.secrets.iter().map(|(name, value)| (name.clone(), value.clone())));
+        Cow::Owned(variables)
+    }
+}
+
+impl Workflow {
+    /// SHA-256 of the step definitions. Resuming compares it against the hash
+    /// stored in the execution record to detect changed steps.
+    pub fn definition_hash(&self) -> Result<String> {
+        // Going through `Value` sorts object keys, so the hash is stable. `finally`
+        // only takes part when present, so records of older workflows still match
+        let mut definition = serde_json::to_value(&self.steps)?;
+        if !self.finally.is_empty() {
+            definition = serde_json::json!({ "steps": definition, "finally": self.finally });
+        }
+        let canonical = definition.to_string();
+        Ok(format!("{:x}", Sha256::digest(canonical.as_bytes())))
+    }
+
+    /// SHA-256 of the whole resolved workflow, so it identifies exactly what
+    /// ran whatever file format or includes it came from.
+    pub fn content_hash(&self) -> Result<String> {
+        let canonical = serde_json::to_value(self)?.to_string();
+        Ok(format!("{:x}", Sha256::digest(canonical.as_bytes())))
+    }
 }
 
 impl WorkflowEngine {
     pub fn new(config: WorkflowConfig) -> Self {
-        Self { config }
+        let step_slots = Arc::new(Semaphore::new(config.max_parallel_steps.max(1)));
+        Self {
+            config,
+            step_slots,
+            upload_manager: None,
+            cache: None,
+            active_executions: DashMap::new(),
+        }
+    }
+
+    /// Lets `Upload` steps hand files to `upload_manager`.
+    pub fn with_upload_manager(mut self, upload_manager: UploadManager) -> Self {
+        self.upload_manager = Some(upload_manager);
+        self
+    }
+
+    /// Lets steps with a `cache` section reuse earlier results from `cache`.
+    pub fn with_step_cache(mut self, cache: StepCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn step_cache(&self) -> Option<&StepCache> {
+        self.cache.as_ref()
     }
 
     pub async fn execute_workflow(&self, workflow_path: &str) -> Result<WorkflowExecution> {
+        self.execute_workflow_with_inputs(workflow_path, &HashMap::new()).await
+    }
+
+    /// Runs the workflow with values for its declared inputs (or to override its
+    /// variables). Missing or invalid inputs reject the run before any step starts.
+    pub async fn execute_workflow_with_inputs(
+        &self,
+        workflow_path: &str,
+        inputs: &HashMap<String, String>,
+    ) -> Result<WorkflowExecution> {
         let workflow = self.load_workflow(workflow_path).await?;
+        let execution = self.new_execution(&workflow, workflow_path, inputs, false)?;
+        self.run_execution(&workflow, execution).await
+    }
+
+    /// A pending execution of `workflow`; see `inputs::resolve` for `allow_undeclared`.
+    fn new_execution(
+        &self,
+        workflow: &Workflow,
+        workflow_path: &str,
+        inputs: &HashMap<String, String>,
+        allow_undeclared: bool,
+    ) -> Result<WorkflowExecution> {
+        let variables = inputs::resolve(workflow, inputs, allow_undeclared)?;
+        let secrets = secrets::resolve(workflow, &self.config)?;
         let execution_id = Uuid::new_v4();
         
         info!("Starting workflow execution {}: {}", execution_id, workflow.name);
 
-        let mut execution = WorkflowExecution {
+        Ok(WorkflowExecution {
             id: execution_id,
             workflow_id: workflow.id,
             status: ExecutionStatus::Pending,
             started_at: Utc::now(),
             completed_at: None,
             steps_executed: Vec::new(),
-            variables: workflow.variables.clone(),
+            variables,
             error_message: None,
-        };
+            // Absolute, so the execution can be resumed from another directory
+            workflow_path: Some(fs::canonicalize(workflow_path)?.display().to_string()),
+            definition_hash: Some(workflow.definition_hash()?),
+            workflow_version: Some(workflow.version.clone()),
+            content_hash: Some(workflow.content_hash()?),
+            resume_count: 0,
+            deadline: None,
+            parent_execution_id: None,
+            estimate_exceeded_at: None,
+            secrets,
+        })
+    }
+
+    /// Continues a failed, cancelled or interrupted execution. Steps that already
+    /// completed (or were skipped) are not run again and their outputs are
+    /// restored; everything else runs as usual. Refuses to resume when the
+    /// workflow's steps changed since the execution started.
+    pub async fn resume(&self, execution_id: Uuid) -> Result<WorkflowExecution> {
+        let mut execution = self
+            .get_execution(execution_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Execution record not found: {}", execution_id))?;
+
+        match execution.status {
+            ExecutionStatus::Completed => {
+                return Err(anyhow::anyhow!("Execution {} already completed", execution_id));
+            }
+            _ if self.active_executions.contains_key(&execution_id) => {
+                return Err(anyhow::anyhow!("Execution {} is still running", execution_id));
+            }
+            _ => {}
+        }
+
+        let workflow_path = execution
+            .workflow_path
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Execution {} does not record its workflow file", execution_id))?;
+        let workflow = self.load_workflow(&workflow_path).await?;
 
-        // Execute workflow steps
-        self.execute_workflow_steps(&workflow, &mut execution).await?;
+        let definition_hash = workflow.definition_hash()?;
+        if execution.definition_hash.as_deref() != Some(definition_hash.as_str()) {
+            return Err(anyhow::anyhow!(
+                "Workflow {} changed since execution {} started; start a new execution instead",
+                workflow_path, execution_id
+            ));
+        }
+        execution.secrets = secrets::resolve(&workflow, &self.config)?;
+
+        // Keep finished steps whose dependencies were kept too and restore what they
+        // published; the rest run again. This re-evaluates steps that were skipped or
+        // ran because of the failure, and always reruns the `finally` block
+        let mut kept = HashSet::new();
+        for step in self.sort_steps_by_dependencies(&workflow.steps)? {
+            let done = execution
+                .steps_executed
+                .iter()
+                .any(|step_execution| step_execution.step_id == step.id && step_execution.is_done());
+            if done && step.depends_on.iter().all(|dep| kept.contains(dep)) {
+                kept.insert(step.id.clone());
+            }
+        }
+        execution.steps_executed.retain(|step_execution| {
+            kept.contains(&step_execution.step_id)
+                || step_execution.instance_of.as_ref().is_some_and(|parent| kept.contains(parent))
+        });
+        for step_execution in &execution.steps_executed {
+            let id = step_execution.instance_of.as_ref().unwrap_or(&step_execution.step_id);
+            let Some(step) = workflow.steps.iter().find(|step| &step.id == id) else {
+                continue;
+            };
+            if step_execution.instance_of.is_some() {
+                // Instances publish under their own id only, see `expansion::expand`
+                let instance = WorkflowStep {
+                    id: step_execution.step_id.clone(),
+                    output: None,
+                    ..step.clone()
+                };
+                Self::publish_step_results(&instance, step_execution, &mut execution.variables);
+            } else {
+                Self::publish_step_results(step, step_execution, &mut execution.variables);
+            }
+        }
+        execution.status = ExecutionStatus::Pending;
+        execution.completed_at = None;
+        execution.error_message = None;
+        execution.resume_count += 1;
+
+        info!(
+            "Resuming workflow execution {} ({} of {} steps already done)",
+            execution_id,
+            execution.steps_executed.len(),
+            workflow.steps.len()
+        );
+        self.run_execution(&workflow, execution).await
+    }
+
+    async fn run_execution(
+        &self,
+        workflow: &Workflow,
+        mut execution: WorkflowExecution,
+    ) -> Result<WorkflowExecution> {
+        let execution_id = execution.id;
+        let run_started = Utc::now();
+
+        let timeout = workflow.timeout_seconds.or(self.config.workflow_timeout_seconds);
+        execution.deadline = timeout.map(|seconds| Utc::now() + chrono::Duration::seconds(seconds as i64));
+
+        // Save the record before any step runs so even a crashed run leaves a trace
+        self.save_execution_record(&execution).await?;
+
+        // Warn (once) when the run takes longer than the workflow expects
+        let overrun_warning = workflow.metadata.estimated_duration.map(|estimated| {
+            let name = workflow.name.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(estimated)).await;
+                warn!(
+                    "Workflow execution {} ({}) is running longer than its estimated duration of {}s",
+                    execution_id, name, estimated
+                );
+            })
+        });
+
+        let (cancel_sender, cancel_receiver) = watch::channel(false);
+        self.active_executions.insert(execution_id, cancel_sender);
+        let mut active_run = ActiveRun {
+            engine: self,
+            execution_id,
+            overrun_warning,
+            finished: false,
+        };
+        let result = self
+            .execute_workflow_steps(workflow, &mut execution, cancel_receiver)
+            .await;
+        active_run.finished = true;
+        drop(active_run);
 
         execution.completed_at = Some(Utc::now());
-        execution.status = ExecutionStatus::Completed;
+        if let Some(estimated) = workflow.metadata.estimated_duration {
+            let expected_by = run_started + chrono::Duration::seconds(estimated as i64);
+            if execution.completed_at > Some(expected_by) {
+                execution.estimate_exceeded_at = Some(expected_by);
+            }
+        }
+        if let Err(e) = &result {
+            if matches!(execution.status, ExecutionStatus::Pending) || execution.status.is_in_progress() {
+                execution.status = ExecutionStatus::Failed;
+            }
+            if execution.error_message.is_none() {
+                execution.error_message = Some(execution.secrets.redact(&e.to_string()));
+            }
+        }
 
         // Save execution record
         self.save_execution_record(&execution).await?;
 
-        info!("Workflow execution {} completed successfully", execution_id);
+        result?;
+        match execution.status {
+            ExecutionStatus::CompletedWithErrors => {
+                warn!("Workflow execution {} completed with errors", execution_id)
+            }
+            _ => info!("Workflow execution {} completed successfully", execution_id),
+        }
         Ok(execution)
     }
 
+    /// Asks a running execution to stop. Steps in flight have their processes
+    /// terminated and the record is finalized as `Cancelled`.
+    pub fn cancel_execution(&self, execution_id: Uuid) -> bool {
+        match self.active_executions.get(&execution_id) {
+            Some(sender) => {
+                info!("Cancelling workflow execution {}", execution_id);
+                sender.send_replace(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cancels every execution currently running on this engine.
+    pub fn cancel_all_executions(&self) {
+        for entry in self.active_executions.iter() {
+            info!("Cancelling workflow execution {}", entry.key());
+            entry.value().send_replace(true);
+        }
+    }
+
+    pub fn active_executions(&self) -> Vec<Uuid> {
+        self.active_executions.iter().map(|entry| *entry.key()).collect()
+    }
+
     async fn load_workflow(&self, workflow_path: &str) -> Result<Workflow> {
         let path = Path::new(workflow_path);
         
@@ -152,9 +779,37 @@ impl WorkflowEngine {
             return Err(anyhow::anyhow!("Workflow file does not exist: {}", workflow_path));
         }
 
-        let content = fs::read_to_string(path)?;
-        let workflow: Workflow = serde_json::from_str(&content)?;
-        
+        let workflow = format::read_workflow(path)?;
+
+        // Reject malformed conditions, retry policies and the like up front instead
+        // of when the step is reached
+        for step in workflow.steps.iter().chain(&workflow.finally) {
+            if let Some((_, problem)) = validation::step_problems(step, &workflow).into_iter().next() {
+                return Err(anyhow::anyhow!("Step {} in {}: {}", step.id, workflow_path, problem));
+            }
+        }
+        inputs::validate_declarations(&workflow)
+            .and_then(|()| secrets::validate_declarations(&workflow))
+            .map_err(|e| anyhow::anyhow!("Workflow {}: {}", workflow_path, e))?;
+        self.check_sub_workflow_cycles(&workflow, &mut vec![fs::canonicalize(path)?])?;
+        for step in &workflow.finally {
+            if workflow.steps.iter().any(|other| other.id == step.id) {
+                return Err(anyhow::anyhow!(
+                    "Finally step {} in {} reuses the id of a workflow step", step.id, workflow_path
+                ));
+            }
+            if let Some(dep) = step
+                .depends_on
+                .iter()
+                .find(|dep| !workflow.finally.iter().any(|other| &other.id == *dep))
+            {
+                return Err(anyhow::anyhow!(
+                    "Finally step {} in {} depends on {}, which is not a finally step",
+                    step.id, workflow_path, dep
+                ));
+            }
+        }
+
         info!("Loaded workflow: {} (version: {})", workflow.name, workflow.version);
         Ok(workflow)
     }
@@ -163,34 +818,438 @@ impl WorkflowEngine {
         &self,
         workflow: &Workflow,
         execution: &mut WorkflowExecution,
+        cancel: watch::Receiver<bool>,
     ) -> Result<()> {
         execution.status = ExecutionStatus::Running;
 
-        // Sort steps by dependencies
-        let sorted_steps = self.sort_steps_by_dependencies(&workflow.steps)?;
+        // Whether each finished step counts as succeeded for the steps depending on it.
+        // Steps finished by an earlier attempt of a resumed execution are not run again
+        let mut healthy: HashMap<String, bool> = execution
+            .steps_executed
+            .iter()
+            .filter(|step_execution| step_execution.is_done())
+            .map(|step_execution| (step_execution.step_id.clone(), true))
+            .collect();
 
-        for step in sorted_steps {
-            let step_execution = self.execute_step(step, execution).await?;
-            execution.steps_executed.push(step_execution);
+        let deadline = execution.deadline.map(|deadline| tokio::time::Instant::now() + time_until(deadline));
+        let main = self
+            .run_steps(&workflow.steps, workflow, execution, &mut healthy, cancel, deadline)
+            .await?;
 
-            // Check if any step failed
-            if let Some(failed_step) = execution.steps_executed.iter().find(|s| {
-                matches!(s.status, ExecutionStatus::Failed)
-            }) {
-                execution.status = ExecutionStatus::Failed;
-                execution.error_message = failed_step.error_message.clone();
-                return Err(anyhow::anyhow!("Step {} failed: {:?}", 
-                    failed_step.step_id, failed_step.error_message));
-            }
+        // The finally block runs even after a failure, cancellation or the deadline,
+        // so it gets a cancellation signal of its own that never fires
+        let mut finally = StepsOutcome::default();
+        if !workflow.finally.is_empty() {
+            info!("Running {} finally step(s) for execution {}", workflow.finally.len(), execution.id);
+            let (_never_cancelled, not_cancelled) = watch::channel(false);
+            finally = self
+                .run_steps(&workflow.finally, workflow, execution, &mut healthy, not_cancelled, None)
+                .await?;
+        }
+
+        if main.cancelled {
+            execution.status = ExecutionStatus::Cancelled;
+            execution.error_message = Some("Workflow execution cancelled".to_string());
+            return Err(anyhow::anyhow!("Workflow execution {} cancelled", execution.id));
+        }
+
+        if main.deadline_exceeded {
+            let message = format!(
+                "Workflow execution {} exceeded its deadline of {}",
+                execution.id,
+                execution.deadline.map(|deadline| deadline.to_rfc3339()).unwrap_or_default()
+            );
+            execution.status = ExecutionStatus::TimedOut;
+            execution.error_message = Some(message.clone());
+            return Err(anyhow::anyhow!(message));
+        }
+
+        if let Some(failed_step) = main.failed_step.or(finally.failed_step) {
+            execution.status = if failed_step.termination_reason.is_some() {
+                ExecutionStatus::TimedOut
+            } else {
+                ExecutionStatus::Failed
+            };
+            execution.error_message = failed_step.error_message.clone();
+            return Err(anyhow::anyhow!("Step {} failed: {:?}",
+                failed_step.step_id, failed_step.error_message));
         }
 
+        execution.status = if main.tolerated_failures + finally.tolerated_failures > 0 {
+            ExecutionStatus::CompletedWithErrors
+        } else {
+            ExecutionStatus::Completed
+        };
         Ok(())
     }
 
+    /// Runs `steps` in dependency order, several at a time, recording every result
+    /// in `execution`. Steps already in `healthy` count as finished. Once a step
+    /// fails, the remaining `OnSuccess` steps are skipped while `OnFailure` and
+    /// `Always` steps still run; only cancellation or `deadline` stops the batch early.
+    /// `for_each` and `matrix` steps are expanded when they become ready and finish
+    /// once all of their instances have.
+    async fn run_steps(
+        &self,
+        steps: &[WorkflowStep],
+        workflow: &Workflow,
+        execution: &mut WorkflowExecution,
+        healthy: &mut HashMap<String, bool>,
+        mut cancel: watch::Receiver<bool>,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<StepsOutcome> {
+        // Sort steps by dependencies; this also rejects cycles and unknown dependencies
+        let mut pending = self.sort_steps_by_dependencies(steps)?;
+        pending.retain(|step| !healthy.contains_key(&step.id));
+
+        let parallelism = workflow
+            .max_parallel_steps
+            .unwrap_or(self.config.max_parallel_steps)
+            .max(1);
+        let mut running = FuturesUnordered::new();
+        // Running `Wait` and `Approval` steps, which do not count towards `parallelism`
+        let mut waiting = 0;
+        let mut groups: BTreeMap<String, InstanceGroup> = BTreeMap::new();
+        let mut outcome = StepsOutcome::default();
+
+        let start = |step: WorkflowStep, snapshot: WorkflowExecution, instance_of: Option<String>| {
+            let step_slots = Arc::clone(&self.step_slots);
+            async move {
+                // Only a batch that observes the deadline caps its steps by it; the
+                // finally block runs to completion even after the deadline passed
+                let mut snapshot = snapshot;
+                if deadline.is_none() {
+                    snapshot.deadline = None;
+                }
+                // Waiting steps do not hold a slot, see `StepType::waits`, and neither
+                // do `Workflow` steps, whose child steps need slots of their own
+                let _permit = if step.step_type.waits() || matches!(step.step_type, StepType::Workflow) {
+                    None
+                } else {
+                    Some(step_slots.acquire_owned().await?)
+                };
+                let mut step_execution = self.execute_step(&step, &snapshot).await?;
+                step_execution.instance_of = instance_of;
+                Ok::<_, anyhow::Error>((step, step_execution))
+            }
+        };
+
+        loop {
+            let mut changed = false;
+            loop {
+                // Start (or skip) every step whose dependencies have all finished
+                let mut index = 0;
+                while index < pending.len() && running.len() - waiting < parallelism {
+                    if !pending[index].depends_on.iter().all(|dep| healthy.contains_key(dep)) {
+                        index += 1;
+                        continue;
+                    }
+                    let step = pending.remove(index);
+                    changed = true;
+
+                    let upstream_failed = step.depends_on.iter().any(|dep| healthy.get(dep) == Some(&false));
+                    let skip_reason = match step.run_if {
+                        RunCondition::OnSuccess if upstream_failed => Some("a dependency failed"),
+                        RunCondition::OnSuccess if outcome.failed_step.is_some() => Some("the workflow failed"),
+                        RunCondition::OnFailure if !upstream_failed => Some("no dependency failed"),
+                        _ => None,
+                    };
+                    if let Some(reason) = skip_reason {
+                        info!("Step {} skipped because {}", step.id, reason);
+                        let skipped = StepExecution::skipped(&step.id, reason);
+                        Self::publish_step_results(step, &skipped, &mut execution.variables);
+                        // A step skipped because of a failure passes it on to its dependents
+                        healthy.insert(step.id.clone(), !upstream_failed);
+                        Self::record_step(execution, skipped);
+                        continue;
+                    }
+
+                    if !step.is_expanded() {
+                        Self::record_step(execution, StepExecution::started(step));
+                        waiting += usize::from(step.step_type.waits());
+                        running.push(start(step.clone(), execution.clone(), None));
+                        continue;
+                    }
+
+                    match expansion::expand(step, &execution.variables) {
+                        Ok(instances) if instances.is_empty() => {
+                            info!("Step {} skipped because it has nothing to iterate over", step.id);
+                            let skipped = StepExecution::skipped(&step.id, "it has nothing to iterate over");
+                            Self::finish_step(step, skipped, execution, healthy, &mut outcome);
+                        }
+                        Ok(instances) => {
+                            info!("Step {} expands into {} instances", step.id, instances.len());
+                            let record = StepExecution::running(&step.id);
+                            Self::record_step(execution, record.clone());
+                            groups.insert(step.id.clone(), InstanceGroup {
+                                step,
+                                record,
+                                order: instances.iter().map(|instance| instance.step.id.clone()).collect(),
+                                queued: instances.into(),
+                                running: 0,
+                                failed: false,
+                                finished: HashMap::new(),
+                            });
+                        }
+                        Err(e) => {
+                            error!("Step {} failed to expand: {}", step.id, e);
+                            let mut failed = StepExecution::running(&step.id);
+                            failed.status = ExecutionStatus::Failed;
+                            failed.error_message = Some(e.to_string());
+                            failed.completed_at = Some(Utc::now());
+                            Self::finish_step(step, failed, execution, healthy, &mut outcome);
+                        }
+                    }
+                }
+
+                // Start queued instances, unless a failure means the rest should not run
+                for group in groups.values_mut() {
+                    let stop_reason = if group.failed && !group.step.continue_on_error {
+                        Some("an earlier instance failed")
+                    } else if outcome.failed_step.is_some() && group.step.run_if.is_on_success() {
+                        Some("the workflow failed")
+                    } else {
+                        None
+                    };
+                    if let Some(reason) = stop_reason {
+                        for instance in group.queued.drain(..) {
+                            let mut skipped = StepExecution::skipped(&instance.step.id, reason);
+                            skipped.instance_of = Some(group.step.id.clone());
+                            Self::record_step(execution, skipped.clone());
+                            group.finished.insert(instance.step.id, skipped);
+                            changed = true;
+                        }
+                    }
+
+                    let cap = group.step.max_parallel.unwrap_or(usize::MAX);
+                    while group.running < cap && running.len() - waiting < parallelism {
+                        let Some(instance) = group.queued.pop_front() else { break };
+                        let mut record = StepExecution::started(&instance.step);
+                        record.instance_of = Some(group.step.id.clone());
+                        Self::record_step(execution, record);
+
+                        let mut snapshot = execution.clone();
+                        snapshot.variables.extend(instance.variables);
+                        waiting += usize::from(instance.step.step_type.waits());
+                        running.push(start(instance.step, snapshot, Some(group.step.id.clone())));
+                        group.running += 1;
+                        changed = true;
+                    }
+                }
+
+                // A step whose instances all finished may unblock its dependents
+                let done: Vec<String> = groups
+                    .iter()
+                    .filter(|(_, group)| group.queued.is_empty() && group.running == 0)
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                if done.is_empty() {
+                    break;
+                }
+                for id in done {
+                    let group = groups.remove(&id).expect("finished group is tracked");
+                    let step_execution = Self::combine_instances(&group);
+                    Self::finish_step(group.step, step_execution, execution, healthy, &mut outcome);
+                }
+            }
+            if changed {
+                Self::update_waiting_status(execution);
+                self.checkpoint(execution).await;
+            }
+
+            let next = tokio::select! {
+                next = running.next() => next,
+                _ = cancel.wait_for(|cancelled| *cancelled) => {
+                    outcome.cancelled = true;
+                    break;
+                }
+                _ = sleep_until(deadline) => {
+                    warn!("Workflow execution {} exceeded its deadline", execution.id);
+                    outcome.deadline_exceeded = true;
+                    break;
+                }
+            };
+            let (step, step_execution) = match next {
+                Some(result) => result?,
+                None => break,
+            };
+            waiting -= usize::from(step.step_type.waits());
+
+            match step_execution.instance_of.clone() {
+                Some(parent) => {
+                    Self::publish_step_results(&step, &step_execution, &mut execution.variables);
+                    Self::record_step(execution, step_execution.clone());
+                    if let Some(group) = groups.get_mut(&parent) {
+                        group.running -= 1;
+                        group.failed |= matches!(step_execution.status, ExecutionStatus::Failed);
+                        group.finished.insert(step_execution.step_id.clone(), step_execution);
+                    }
+                }
+                None => Self::finish_step(&step, step_execution, execution, healthy, &mut outcome),
+            }
+            Self::update_waiting_status(execution);
+            self.checkpoint(execution).await;
+        }
+
+        if outcome.cancelled || outcome.deadline_exceeded {
+            let reason = if outcome.cancelled { "workflow cancelled" } else { "workflow deadline exceeded" };
+            // Dropping the in-flight step futures terminates their process groups
+            drop(running);
+            for step_execution in &mut execution.steps_executed {
+                if step_execution.status.is_in_progress() {
+                    step_execution.status = ExecutionStatus::Cancelled;
+                    step_execution.completed_at = Some(Utc::now());
+                    step_execution.termination_reason = Some(reason.to_string());
+                }
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    /// Publishes and records a finished step and decides what its result means
+    /// for its dependents and the workflow.
+    fn finish_step(
+        step: &WorkflowStep,
+        step_execution: StepExecution,
+        execution: &mut WorkflowExecution,
+        healthy: &mut HashMap<String, bool>,
+        outcome: &mut StepsOutcome,
+    ) {
+        Self::publish_step_results(step, &step_execution, &mut execution.variables);
+        let failed = matches!(step_execution.status, ExecutionStatus::Failed);
+        if failed && step.continue_on_error {
+            warn!("Step {} failed; continuing because it sets continue_on_error", step.id);
+            outcome.tolerated_failures += 1;
+        } else if failed && outcome.failed_step.is_none() {
+            outcome.failed_step = Some(step_execution.clone());
+        }
+        healthy.insert(step.id.clone(), !failed || step.continue_on_error);
+        Self::record_step(execution, step_execution);
+    }
+
+    /// The result of a `for_each`/`matrix` step: failed if any instance failed,
+    /// with the instances' outputs joined one per line in instance order.
+    fn combine_instances(group: &InstanceGroup) -> StepExecution {
+        let instances: Vec<&StepExecution> = group
+            .order
+            .iter()
+            .filter_map(|id| group.finished.get(id))
+            .collect();
+        let failures: Vec<&&StepExecution> = instances
+            .iter()
+            .filter(|instance| matches!(instance.status, ExecutionStatus::Failed))
+            .collect();
+
+        let mut combined = group.record.clone();
+        combined.completed_at = Some(Utc::now());
+        combined.output = Some(
+            instances
+                .iter()
+                .filter_map(|instance| instance.output.as_deref())
+                .map(str::trim_end)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+        combined.variables.insert("instances".to_string(), instances.len().to_string());
+        combined.variables.insert("failed".to_string(), failures.len().to_string());
+        combined.artifacts = instances
+            .iter()
+            .flat_map(|instance| instance.artifacts.iter().cloned())
+            .collect();
+        if !combined.artifacts.is_empty() {
+            combined.variables.insert(
+                "artifacts".to_string(),
+                artifacts::artifact_variables(&combined.artifacts)["artifacts"].clone(),
+            );
+        }
+        combined.status = match failures.first() {
+            Some(first) => {
+                combined.error_message = Some(format!(
+                    "{} of {} instances failed; {}: {}",
+                    failures.len(),
+                    instances.len(),
+                    first.step_id,
+                    first.error_message.as_deref().unwrap_or("unknown error")
+                ));
+                combined.termination_reason = first.termination_reason.clone();
+                ExecutionStatus::Failed
+            }
+            None if instances.iter().all(|instance| matches!(instance.status, ExecutionStatus::Skipped)) => {
+                ExecutionStatus::Skipped
+            }
+            None => ExecutionStatus::Completed,
+        };
+        combined
+    }
+
+    /// Inserts `step_execution` into the record, replacing the entry for the same step.
+    fn record_step(execution: &mut WorkflowExecution, step_execution: StepExecution) {
+        match execution
+            .steps_executed
+            .iter_mut()
+            .find(|existing| existing.step_id == step_execution.step_id)
+        {
+            Some(existing) => *existing = step_execution,
+            None => execution.steps_executed.push(step_execution),
+        }
+    }
+
+    /// Shows a running execution as `WaitingForApproval` while any of its gates is open.
+    fn update_waiting_status(execution: &mut WorkflowExecution) {
+        if !execution.status.is_in_progress() {
+            return;
+        }
+        let waiting = execution
+            .steps_executed
+            .iter()
+            .any(|step_execution| matches!(step_execution.status, ExecutionStatus::WaitingForApproval));
+        execution.status = if waiting { ExecutionStatus::WaitingForApproval } else { ExecutionStatus::Running };
+    }
+
+    /// Persists the in-progress record. A failed write is logged rather than
+    /// aborting the run.
+    async fn checkpoint(&self, execution: &WorkflowExecution) {
+        if let Err(e) = self.save_execution_record(execution).await {
+            warn!("Failed to save execution record {}: {}", execution.id, e);
+        }
+    }
+
+    /// Exposes a finished step's output, exit code and status to later steps as
+    /// `steps.<id>.*` and, when the step names one, under its `output` variable.
+    fn publish_step_results(
+        step: &WorkflowStep,
+        step_execution: &StepExecution,
+        variables: &mut HashMap<String, String>,
+    ) {
+        let output = step_execution
+            .output
+            .as_deref()
+            .map(|output| output.trim_end().to_string())
+            .unwrap_or_default();
+
+        let mut prefixes = vec![format!("steps.{}", step.id)];
+        variables.insert(format!("steps.{}.output", step.id), output.clone());
+        if let Some(name) = &step.output {
+            variables.insert(name.clone(), output);
+            prefixes.push(name.clone());
+        }
+
+        for prefix in prefixes {
+            variables.insert(format!("{}.status", prefix), step_execution.status.to_string());
+            if let Some(exit_code) = step_execution.exit_code {
+                variables.insert(format!("{}.exit_code", prefix), exit_code.to_string());
+            }
+            for (key, value) in &step_execution.variables {
+                variables.insert(format!("{}.{}", prefix, key), value.clone());
+            }
+        }
+    }
+
     fn sort_steps_by_dependencies<'a>(&self, steps: &'a [WorkflowStep]) -> Result<Vec<&'a WorkflowStep>> {
         let mut sorted = Vec::new();
-        let mut visited = std::collections::HashSet::new();
-        let mut visiting = std::collections::HashSet::new();
+        let mut visited = HashSet::new();
+        let mut visiting = HashSet::new();
 
         for step in steps {
             if !visited.contains(&step.id) {
@@ -205,8 +1264,8 @@ impl WorkflowEngine {
         &self,
         step: &'a WorkflowStep,
         all_steps: &'a [WorkflowStep],
-        visited: &mut std::collections::HashSet<String>,
-        visiting: &mut std::collections::HashSet<String>,
+        visited: &mut HashSet<String>,
+        visiting: &mut HashSet<String>,
         sorted: &mut Vec<&'a WorkflowStep>,
     ) -> Result<()> {
         if visiting.contains(&step.id) {
@@ -237,15 +1296,19 @@ impl WorkflowEngine {
         step: &WorkflowStep,
         execution: &WorkflowExecution,
     ) -> Result<StepExecution> {
-        let mut step_execution = StepExecution {
-            step_id: step.id.clone(),
-            status: ExecutionStatus::Pending,
-            started_at: Utc::now(),
-            completed_at: None,
-            output: None,
-            error_message: None,
-            retry_count: 0,
-        };
+        let mut step_execution = self.attempt_step(step, execution).await?;
+        // Secret values must not reach later steps or the execution record
+        execution.secrets.redact_step(&mut step_execution);
+        Ok(step_execution)
+    }
+
+    async fn attempt_step(
+        &self,
+        step: &WorkflowStep,
+        execution: &WorkflowExecution,
+    ) -> Result<StepExecution> {
+        let mut step_execution = StepExecution::running(&step.id);
+        step_execution.status = ExecutionStatus::Pending;
 
         info!("Executing step: {} ({})", step.name, step.id);
 
@@ -254,14 +1317,66 @@ impl WorkflowEngine {
             if !self.evaluate_condition(condition, execution).await? {
                 step_execution.status = ExecutionStatus::Skipped;
                 step_execution.completed_at = Some(Utc::now());
+                step_execution.skip_reason = Some("its condition is false".to_string());
                 info!("Step {} skipped due to condition", step.id);
                 return Ok(step_execution);
             }
         }
 
+        // Resolve variable references before running; an undefined variable fails the step
+        let step = &match self.interpolate_step(step, &execution.step_variables()) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                step_execution.status = ExecutionStatus::Failed;
+                step_execution.error_message = Some(e.to_string());
+                step_execution.completed_at = Some(Utc::now());
+                error!("Step {} failed to resolve variables: {}", step.id, e);
+                return Ok(step_execution);
+            }
+        };
+
         step_execution.status = ExecutionStatus::Running;
 
-        let max_retries = step.retry_count.unwrap_or(self.config.retry_attempts);
+        let cache_key = match (&self.cache, &step.cache) {
+            (Some(_), Some(spec)) => match self.cache_key(step, spec, execution) {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    warn!("Step {} runs without its cache: {}", step.id, e);
+                    None
+                }
+            },
+            _ => None,
+        };
+        if let (Some(key), Some(spec)) = (&cache_key, &step.cache) {
+            match self.restore_cached(step, key, spec, execution.id).await {
+                Ok(Some(cached)) => {
+                    step_execution.status = ExecutionStatus::Cached;
+                    step_execution.output = cached.entry.output;
+                    step_execution.exit_code = cached.entry.exit_code;
+                    step_execution.variables = cached.entry.variables;
+                    if !cached.artifacts.is_empty() {
+                        step_execution.variables.extend(artifacts::artifact_variables(&cached.artifacts));
+                    }
+                    step_execution.artifacts = cached.artifacts;
+                    step_execution.cache_key = Some(key.clone());
+                    step_execution.completed_at = Some(Utc::now());
+                    info!("Step {} restored from cache", step.id);
+                    return Ok(step_execution);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Failed to read the cache of step {}: {}", step.id, e),
+            }
+        }
+
+        // Asking again after a rejection would defeat the gate
+        let max_retries = match step.step_type {
+            StepType::Approval => 0,
+            // A sensor polls on its own; retrying would only restart its wait, and
+            // retrying a sub-workflow reruns all of it as a new child execution
+            StepType::Wait | StepType::Workflow => step.retry_count.unwrap_or(0),
+            _ => step.retry_count.unwrap_or(self.config.retry_attempts),
+        };
+        let policy = step.retry_policy.as_ref().unwrap_or(&self.config.retry_policy);
         let mut last_error = None;
 
         for attempt in 0..=max_retries {
@@ -270,126 +1385,337 @@ impl WorkflowEngine {
                 info!("Retrying step {} (attempt {}/{})", step.id, attempt, max_retries);
             }
 
-            match self.execute_step_command(step, execution).await {
+            let attempt_started = Utc::now();
+            step_execution.termination_reason = None;
+            let (error_message, stderr) = match self.execute_step_command(step, execution).await {
+                Ok(output) if output.success() => {
+                    let collected = match step.artifacts.is_empty() {
+                        true => Ok(Vec::new()),
+                        false => self.collect_artifacts(step, execution.id).await,
+                    };
+                    step_execution.exit_code = output.exit_code;
+                    step_execution.output = Some(output.stdout);
+                    match collected {
+                        Ok(artifacts) => {
+                            step_execution.attempts.push(StepAttempt {
+                                attempt,
+                                started_at: attempt_started,
+                                completed_at: Utc::now(),
+                                exit_code: output.exit_code,
+                                error_message: None,
+                                retry_delay_ms: None,
+                            });
+                            step_execution.variables = output.variables;
+                            step_execution.child_execution_id = output.child_execution_id;
+                            step_execution.approval = output.approval;
+                            if !step.artifacts.is_empty() {
+                                step_execution.variables.extend(artifacts::artifact_variables(&artifacts));
+                            }
+                            step_execution.artifacts = artifacts;
+                            step_execution.status = ExecutionStatus::Completed;
+                            step_execution.completed_at = Some(Utc::now());
+                            info!("Step {} completed successfully", step.id);
+                            if let Some(key) = cache_key {
+                                self.store_cached(step, key, &mut step_execution, execution).await;
+                            }
+                            return Ok(step_execution);
+                        }
+                        // The command succeeded but its outputs are missing, which
+                        // fails the attempt like any other error
+                        Err(e) => {
+                            let error_message = format!("Failed to collect artifacts: {}", e);
+                            (error_message.clone(), error_message)
+                        }
+                    }
+                }
+                Ok(output) if output.approval.is_some() => {
+                    step_execution.exit_code = output.exit_code;
+                    step_execution.variables = output.variables;
+                    step_execution.approval = output.approval;
+                    (output.stderr.clone(), output.stderr)
+                }
                 Ok(output) => {
-                    step_execution.output = Some(output);
-                    step_execution.status = ExecutionStatus::Completed;
-                    step_execution.completed_at = Some(Utc::now());
-                    info!("Step {} completed successfully", step.id);
-                    return Ok(step_execution);
+                    step_execution.exit_code = output.exit_code;
+                    step_execution.child_execution_id = output.child_execution_id;
+                    (format!("Command failed: {}", output.stderr), output.stderr)
                 }
                 Err(e) => {
-                    last_error = Some(e.to_string());
-                    step_execution.error_message = Some(e.to_string());
-                    
-                    if attempt < max_retries {
-                        // Wait before retry
-                        tokio::time::sleep(tokio::time::Duration::from_secs(2_u64.pow(attempt as u32))).await;
+                    step_execution.exit_code = None;
+                    if let Some(terminated) = e.downcast_ref::<process::Terminated>() {
+                        step_execution.termination_reason = Some(terminated.reason.clone());
                     }
+                    (e.to_string(), e.to_string())
                 }
+            };
+
+            step_execution.attempts.push(StepAttempt {
+                attempt,
+                started_at: attempt_started,
+                completed_at: Utc::now(),
+                exit_code: step_execution.exit_code,
+                error_message: Some(error_message.clone()),
+                retry_delay_ms: None,
+            });
+            last_error = Some(error_message.clone());
+            step_execution.error_message = Some(error_message);
+
+            if attempt == max_retries {
+                break;
             }
+            if !policy.is_retryable(step_execution.exit_code, &stderr) {
+                info!("Step {} failed with a non-retryable error", step.id);
+                break;
+            }
+            let delay = policy.delay(attempt + 1);
+            if !policy.within_deadline(step_execution.started_at, delay) {
+                info!("Step {} exhausted its retry deadline", step.id);
+                break;
+            }
+            if let Some(record) = step_execution.attempts.last_mut() {
+                record.retry_delay_ms = Some(delay.as_millis() as u64);
+            }
+            tokio::time::sleep(delay).await;
         }
 
         step_execution.status = ExecutionStatus::Failed;
         step_execution.completed_at = Some(Utc::now());
-        error!("Step {} failed after {} attempts: {:?}", step.id, max_retries, last_error);
+        error!(
+            "Step {} failed after {} attempts: {:?}",
+            step.id,
+            step_execution.attempts.len(),
+            last_error.map(|error| execution.secrets.redact(&error))
+        );
 
         Ok(step_execution)
     }
 
+    /// Returns a copy of `step` with variable references in its string fields
+    /// substituted and its timeout resolved.
+    fn interpolate_step(
+        &self,
+        step: &WorkflowStep,
+        variables: &HashMap<String, String>,
+    ) -> Result<WorkflowStep> {
+        // Values spliced into shell script text must not be able to change its syntax.
+        // A `Script` step's args become positional parameters, so only its body is quoted.
+        let quote_for = |is_script_text: bool| -> fn(&str) -> String {
+            if is_script_text {
+                interpolation::shell_quote
+            } else {
+                str::to_string
+            }
+        };
+        let command_quote = quote_for(step.shell || matches!(step.step_type, StepType::Script));
+        let args_quote = quote_for(step.shell);
+        let interpolate = |template: &str| interpolation::interpolate(template, variables);
+
+        let mut resolved = step.clone();
+        resolved.name = interpolate(&step.name)?;
+        resolved.command = interpolation::interpolate_with(&step.command, variables, command_quote)?;
+        resolved.args = step
+            .args
+            .iter()
+            .map(|arg| interpolation::interpolate_with(arg, variables, args_quote))
+            .collect::<Result<_>>()?;
+        resolved.timeout = step
+            .timeout
+            .as_ref()
+            .map(|timeout| timeout.resolve(variables).map(Templated::Value))
+            .transpose()?;
+        resolved.working_dir = step.working_dir.as_deref().map(interpolate).transpose()?;
+        resolved.stdin = step.stdin.as_deref().map(interpolate).transpose()?;
+        resolved.cache = step
+            .cache
+            .as_ref()
+            .map(|spec| {
+                Ok::<_, anyhow::Error>(CacheSpec {
+                    files: spec.files.iter().map(|pattern| interpolate(pattern)).collect::<Result<_>>()?,
+                    ..spec.clone()
+                })
+            })
+            .transpose()?;
+        resolved.artifacts = step
+            .artifacts
+            .iter()
+            .map(|pattern| interpolate(pattern))
+            .collect::<Result<_>>()?;
+        resolved.env = step
+            .env
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), interpolate(value)?)))
+            .collect::<Result<_>>()?;
+        resolved.inputs = step
+            .inputs
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), interpolate(value)?)))
+            .collect::<Result<_>>()?;
+        Ok(resolved)
+    }
+
     async fn execute_step_command(
         &self,
         step: &WorkflowStep,
         execution: &WorkflowExecution,
-    ) -> Result<String> {
-        let timeout = step.timeout.unwrap_or(self.config.timeout_seconds);
-        
-        let output = tokio::time::timeout(
-            tokio::time::Duration::from_secs(timeout),
-            self.run_command(&step.command, &step.args, execution)
-        ).await??;
+    ) -> Result<StepOutput> {
+        // A gate's timeout bounds the wait for a decision and then applies `on_timeout`
+        if matches!(step.step_type, StepType::Approval) {
+            return self.dispatch_step(step, execution).await;
+        }
+        let step_timeout = Duration::from_secs(match &step.timeout {
+            Some(timeout) => timeout.resolve(&execution.variables)?,
+            None => self.config.timeout_seconds,
+        });
+        // A step never gets more time than the workflow has left
+        let (timeout, reason) = match execution.deadline.map(time_until) {
+            Some(remaining) if remaining < step_timeout => {
+                (remaining, format!("reached the workflow deadline after {}s", remaining.as_secs()))
+            }
+            _ => (step_timeout, format!("timed out after {}s", step_timeout.as_secs())),
+        };
 
-        Ok(output)
+        // Dropping the step future on timeout terminates any process group it started
+        match tokio::time::timeout(timeout, self.dispatch_step(step, execution)).await {
+            Ok(output) => output,
+            Err(_) => Err(process::Terminated { reason }.into()),
+        }
+    }
+
+    /// Runs the step's own `command` and `args`, through `/bin/sh -c` in shell mode.
+    async fn run_step_command(&self, step: &WorkflowStep, execution: &WorkflowExecution) -> Result<StepOutput> {
+        if step.shell {
+            let script = std::iter::once(step.command.as_str())
+                .chain(step.args.iter().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join(" ");
+            self.run_command(step, "/bin/sh", &["-c".to_string(), script], execution)
+                .await
+        } else {
+            self.run_command(step, &step.command, &step.args, execution).await
+        }
     }
 
     async fn run_command(
         &self,
+        step: &WorkflowStep,
         command: &str,
         args: &[String],
         execution: &WorkflowExecution,
-    ) -> Result<String> {
+    ) -> Result<StepOutput> {
         let mut cmd = Command::new(command);
-        cmd.args(args);
+        cmd.args(args)
+            .stdin(if step.stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
 
-        // Set environment variables from workflow execution
-        for (key, value) in &execution.variables {
-            cmd.env(key, value);
+        if let Some(working_dir) = &step.working_dir {
+            cmd.current_dir(working_dir);
         }
 
-        let output = cmd.output()?;
+        match &step.inherit_env {
+            InheritEnv::All(true) => {}
+            InheritEnv::All(false) => {
+                cmd.env_clear();
+            }
+            InheritEnv::Only(names) => {
+                cmd.env_clear();
+                for name in names {
+                    if let Ok(value) = std::env::var(name) {
+                        cmd.env(name, value);
+                    }
+                }
+            }
+        }
 
-        if output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            Ok(stdout.to_string())
-        } else {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            Err(anyhow::anyhow!("Command failed: {}", stderr))
+        // Set environment variables (and secrets) from workflow execution, then the step's own
+        for (key, value) in execution.step_variables().iter() {
+            cmd.env(key, value);
         }
+        cmd.envs(&step.env);
+
+        let grace_period = Duration::from_secs(self.config.kill_grace_period_seconds);
+        let output = process::run(cmd, step.stdin.clone(), grace_period).await?;
+
+        Ok(StepOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            exit_code: output.status.code(),
+            variables: HashMap::new(),
+            child_execution_id: None,
+            approval: None,
+        })
     }
 
     async fn evaluate_condition(&self, condition: &str, execution: &WorkflowExecution) -> Result<bool> {
-        // Simple condition evaluation - can be extended with a proper expression parser
-        if condition.contains("$") {
-            // Replace variables with their values
-            let mut evaluated_condition = condition.to_string();
-            for (key, value) in &execution.variables {
-                let placeholder = format!("${}", key);
-                evaluated_condition = evaluated_condition.replace(&placeholder, value);
-            }
-            
-            // Simple boolean evaluation
-            Ok(evaluated_condition.to_lowercase() == "true")
-        } else {
-            Ok(true)
-        }
+        Expr::parse(condition)?.is_true(&execution.step_variables())
+    }
+
+    fn execution_record_path(&self, execution_id: Uuid) -> PathBuf {
+        self.config
+            .workflow_dir
+            .join("executions")
+            .join(format!("{}.json", execution_id))
     }
 
     async fn save_execution_record(&self, execution: &WorkflowExecution) -> Result<()> {
         let executions_dir = self.config.workflow_dir.join("executions");
         fs::create_dir_all(&executions_dir)?;
         
-        let record_path = executions_dir.join(format!("{}.json", execution.id));
-        let record_json = serde_json::to_string_pretty(execution)?;
+        let record_path = self.execution_record_path(execution.id);
+        let record_json = execution.secrets.redact_json(&serde_json::to_string_pretty(execution)?);
         fs::write(record_path, record_json)?;
 
         info!("Workflow execution record saved: {}", execution.id);
         Ok(())
     }
 
+    /// Marks the last checkpoint of an execution whose run was dropped midway as
+    /// cancelled. Runs from `Drop`, so it cannot await.
+    fn finalize_interrupted(&self, execution_id: Uuid) {
+        let record_path = self.execution_record_path(execution_id);
+        let finalized = fs::read_to_string(&record_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| {
+                let mut execution: WorkflowExecution = serde_json::from_str(&content)?;
+                for step_execution in &mut execution.steps_executed {
+                    if step_execution.status.is_in_progress() {
+                        step_execution.status = ExecutionStatus::Cancelled;
+                        step_execution.completed_at = Some(Utc::now());
+                        step_execution.termination_reason = Some("workflow interrupted".to_string());
+                    }
+                }
+                execution.status = ExecutionStatus::Cancelled;
+                execution.completed_at = Some(Utc::now());
+                execution.error_message = Some("Workflow execution interrupted".to_string());
+                fs::write(&record_path, serde_json::to_string_pretty(&execution)?)?;
+                Ok(())
+            });
+        match finalized {
+            Ok(()) => warn!("Workflow execution {} was interrupted", execution_id),
+            Err(e) => warn!("Failed to finalize interrupted execution {}: {}", execution_id, e),
+        }
+    }
+
+    /// Every workflow that parses, by name and then version. See `registry` for
+    /// lookups by version and for the files that were skipped.
     pub async fn list_workflows(&self) -> Result<Vec<Workflow>> {
         let mut workflows = Vec::new();
 
-        if self.config.workflow_dir.exists() {
-            for entry in fs::read_dir(&self.config.workflow_dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                
-                if path.extension().map_or(false, |ext| ext == "json") {
-                    if let Ok(content) = fs::read_to_string(&path) {
-                        if let Ok(workflow) = serde_json::from_str::<Workflow>(&content) {
-                            workflows.push(workflow);
-                        }
-                    }
-                }
+        for path in self.workflow_files()? {
+            match format::read_workflow(&path) {
+                Ok(workflow) => workflows.push(workflow),
+                Err(e) => warn!("Skipping workflow {}: {}", path.display(), e),
             }
         }
+        workflows.sort_by(|a, b| {
+            let version = |workflow: &Workflow| semver::Version::parse(&workflow.version).ok();
+            a.name.cmp(&b.name).then_with(|| version(a).cmp(&version(b)))
+        });
 
         Ok(workflows)
     }
 
     pub async fn get_execution(&self, execution_id: Uuid) -> Result<Option<WorkflowExecution>> {
-        let executions_dir = self.config.workflow_dir.join("executions");
-        let record_path = executions_dir.join(format!("{}.json", execution_id));
+        let record_path = self.execution_record_path(execution_id);
 
         if record_path.exists() {
             let content = fs::read_to_string(record_path)?;
@@ -400,3 +1726,16 @@ impl WorkflowEngine {
         }
     }
 }
+
+/// Time left until `deadline`, zero once it has passed.
+fn time_until(deadline: DateTime<Utc>) -> Duration {
+    (deadline - Utc::now()).to_std().unwrap_or_default()
+}
+
+/// Completes at `deadline`, or never without one.
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,358 @@
+use super::{
+    expansion::{self, ForEachSource},
+    inputs,
+    interpolation::{self, Segment},
+    Expr, RunCondition, StepType, Workflow, WorkflowEngine, WorkflowStep,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+use uuid::Uuid;
+
+/// What running a workflow would do, worked out without running anything.
+/// Values only known at runtime, such as step outputs, are left as `${name}`
+/// and secrets are shown as `***`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionPlan {
+    pub workflow_id: Uuid,
+    pub workflow_name: String,
+    pub workflow_path: String,
+    /// Groups of steps that can run in parallel, in order.
+    pub stages: Vec<Vec<PlannedStep>>,
+    /// Stages of the workflow's `finally` steps.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub finally: Vec<Vec<PlannedStep>>,
+    /// The variables the run would start with.
+    pub variables: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedStep {
+    pub id: String,
+    pub name: String,
+    pub step_type: StepType,
+    pub decision: PlanDecision,
+    /// Why the step is skipped or only conditionally run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// The command line for steps that start a process, or the built-in
+    /// operation with its operands.
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_seconds: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// The `for_each`/`matrix` instances, when they can be listed up front.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub instances: Vec<PlannedStep>,
+    /// Problems that would make the step fail, e.g. an undefined variable.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PlanDecision {
+    Run,
+    Skip,
+    /// Depends on values only known at runtime.
+    Conditional,
+}
+
+impl WorkflowEngine {
+    /// Plans a run of the workflow with `inputs`, validating both exactly as
+    /// `execute_workflow_with_inputs` would, but without running any step or
+    /// writing an execution record.
+    pub async fn plan(&self, workflow_path: &str, inputs: &HashMap<String, String>) -> Result<ExecutionPlan> {
+        let workflow = self.load_workflow(workflow_path).await?;
+        let mut variables = inputs::resolve(&workflow, inputs, false)?;
+        let shown_variables = variables.clone().into_iter().collect();
+        // Secrets are not read for a plan
+        for name in workflow.secrets.keys() {
+            variables.insert(name.clone(), "***".to_string());
+        }
+
+        let planner = Planner { workflow: &workflow, variables: &variables };
+        Ok(ExecutionPlan {
+            workflow_id: workflow.id,
+            workflow_name: workflow.name.clone(),
+            workflow_path: workflow_path.to_string(),
+            stages: planner.stages(&workflow.steps)?,
+            finally: planner.stages(&workflow.finally)?,
+            variables: shown_variables,
+            timeout_seconds: workflow.timeout_seconds.or(self.config.workflow_timeout_seconds),
+        })
+    }
+}
+
+struct Planner<'a> {
+    workflow: &'a Workflow,
+    variables: &'a HashMap<String, String>,
+}
+
+impl Planner<'_> {
+    /// Orders `steps` into stages: each step goes one stage after the latest of
+    /// its dependencies.
+    fn stages(&self, steps: &[WorkflowStep]) -> Result<Vec<Vec<PlannedStep>>> {
+        let mut stage_of: HashMap<&str, usize> = HashMap::new();
+        let mut stages: Vec<Vec<PlannedStep>> = Vec::new();
+        while stage_of.len() < steps.len() {
+            let ready: Vec<(&WorkflowStep, usize)> = steps
+                .iter()
+                .filter(|step| !stage_of.contains_key(step.id.as_str()))
+                .filter_map(|step| {
+                    step.depends_on
+                        .iter()
+                        .map(|dependency| stage_of.get(dependency.as_str()).map(|stage| stage + 1))
+                        .try_fold(0, |latest, stage| stage.map(|stage| latest.max(stage)))
+                        .map(|stage| (step, stage))
+                })
+                .collect();
+            if ready.is_empty() {
+                return Err(anyhow::anyhow!("Circular dependency between workflow steps"));
+            }
+            for (step, stage) in ready {
+                stage_of.insert(&step.id, stage);
+                if stages.len() <= stage {
+                    stages.resize_with(stage + 1, Vec::new);
+                }
+                stages[stage].push(self.step(step));
+            }
+        }
+        Ok(stages)
+    }
+
+    fn step(&self, step: &WorkflowStep) -> PlannedStep {
+        let mut warnings = Vec::new();
+        let (decision, reason) = self.decide(step, &mut warnings);
+
+        let mut planned = self.render(step, self.variables, &mut warnings);
+        planned.decision = decision;
+        planned.reason = reason;
+        planned.depends_on = step.depends_on.clone();
+
+        if step.is_expanded() {
+            let runtime_items = step.for_each.as_ref().is_some_and(|for_each| match &for_each.source {
+                ForEachSource::Lines(_) => true,
+                ForEachSource::Variable(name) => self.is_runtime(name),
+                _ => false,
+            });
+            if runtime_items {
+                warnings.push("instances are only known at runtime".to_string());
+            } else {
+                match expansion::expand(step, self.variables) {
+                    Ok(instances) => {
+                        for instance in instances {
+                            let mut variables = self.variables.clone();
+                            variables.extend(instance.variables);
+                            let mut planned_instance = self.render(&instance.step, &variables, &mut warnings);
+                            planned_instance.decision = decision;
+                            planned.instances.push(planned_instance);
+                        }
+                    }
+                    Err(e) => warnings.push(e.to_string()),
+                }
+            }
+        }
+
+        planned.warnings = warnings;
+        planned
+    }
+
+    fn decide(&self, step: &WorkflowStep, warnings: &mut Vec<String>) -> (PlanDecision, Option<String>) {
+        match step.run_if {
+            RunCondition::OnFailure => {
+                return (PlanDecision::Conditional, Some("runs only if a dependency fails".to_string()));
+            }
+            RunCondition::Always | RunCondition::OnSuccess => {}
+        }
+        let Some(condition) = &step.condition else {
+            return (PlanDecision::Run, None);
+        };
+        let expr = match Expr::parse(condition) {
+            Ok(expr) => expr,
+            Err(e) => {
+                warnings.push(e.to_string());
+                return (PlanDecision::Skip, None);
+            }
+        };
+        if expr.variables().iter().any(|name| self.is_runtime(name)) {
+            return (
+                PlanDecision::Conditional,
+                Some(format!("condition '{}' depends on earlier steps", condition)),
+            );
+        }
+        match expr.is_true(self.variables) {
+            Ok(true) => (PlanDecision::Run, None),
+            Ok(false) => (PlanDecision::Skip, Some(format!("condition '{}' is false", condition))),
+            Err(e) => {
+                warnings.push(e.to_string());
+                (PlanDecision::Skip, None)
+            }
+        }
+    }
+
+    /// The step as it would be started, with the variables known up front substituted.
+    fn render(
+        &self,
+        step: &WorkflowStep,
+        variables: &HashMap<String, String>,
+        warnings: &mut Vec<String>,
+    ) -> PlannedStep {
+        let is_script_text = step.shell || matches!(step.step_type, StepType::Script);
+        let mut text = |template: &str, quote: bool| -> String {
+            self.substitute(template, variables, quote).unwrap_or_else(|e| {
+                warnings.push(e.to_string());
+                template.to_string()
+            })
+        };
+
+        let command = text(&step.command, is_script_text);
+        let args: Vec<String> = step.args.iter().map(|arg| text(arg, step.shell)).collect();
+        let quoted_args = || args.iter().map(|arg| interpolation::shell_quote(arg)).collect::<Vec<_>>();
+        let command_line = match step.step_type {
+            StepType::Command | StepType::Transform | StepType::Notify if step.runs_program() => {
+                if step.shell {
+                    let script = std::iter::once(command).chain(args).collect::<Vec<_>>().join(" ");
+                    format!("/bin/sh -c {}", interpolation::shell_quote(&script))
+                } else {
+                    std::iter::once(interpolation::shell_quote(&command))
+                        .chain(quoted_args())
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                }
+            }
+            StepType::Script => std::iter::once(format!(
+                "/bin/sh -c {} {}",
+                interpolation::shell_quote(&command),
+                step.id
+            ))
+            .chain(quoted_args())
+            .collect::<Vec<_>>()
+            .join(" "),
+            StepType::Workflow => {
+                let inputs: Vec<String> = step
+                    .inputs
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, text(value, false)))
+                    .collect();
+                format!("workflow {} {}", command, inputs.join(" ")).trim_end().to_string()
+            }
+            _ => format!("{:?} {} {}", step.step_type, command, quoted_args().join(" "))
+                .trim_end()
+                .to_string(),
+        };
+
+        PlannedStep {
+            id: step.id.clone(),
+            name: text(&step.name, false),
+            step_type: step.step_type.clone(),
+            decision: PlanDecision::Run,
+            reason: None,
+            command: command_line,
+            working_dir: step.working_dir.as_deref().map(|dir| text(dir, false)),
+            env: step.env.iter().map(|(name, value)| (name.clone(), text(value, false))).collect(),
+            timeout_seconds: step.timeout.as_ref().and_then(|timeout| timeout.resolve(variables).ok()),
+            depends_on: Vec::new(),
+            instances: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Like `interpolation::interpolate_with`, but keeps references to values
+    /// produced at runtime as `${name}`.
+    fn substitute(&self, template: &str, variables: &HashMap<String, String>, quote: bool) -> Result<String> {
+        let mut rendered = String::new();
+        for segment in interpolation::parse_with(template, |name| self.is_output(name))? {
+            match segment {
+                Segment::Literal(text) => rendered.push_str(&text),
+                Segment::Variable { name, default } => {
+                    let value = match (variables.get(&name), default) {
+                        (Some(value), _) => value.clone(),
+                        _ if self.is_runtime(&name) => {
+                            rendered.push_str(&format!("${{{}}}", name));
+                            continue;
+                        }
+                        (None, Some(default)) => default,
+                        (None, None) => {
+                            return Err(anyhow::anyhow!("Undefined variable '{}' in '{}'", name, template));
+                        }
+                    };
+                    rendered.push_str(&if quote { interpolation::shell_quote(&value) } else { value });
+                }
+            }
+        }
+        Ok(rendered)
+    }
+
+    /// Whether `name` is set by a step while the workflow runs.
+    fn is_runtime(&self, name: &str) -> bool {
+        name.starts_with("steps.")
+            || self.workflow.steps.iter().chain(&self.workflow.finally).any(|step| {
+                step.output
+                    .as_deref()
+                    .is_some_and(|output| name == output || name.starts_with(&format!("{}.", output)))
+            })
+    }
+
+    /// Whether `name` is the `output` of a step.
+    fn is_output(&self, name: &str) -> bool {
+        self.workflow
+            .steps
+            .iter()
+            .chain(&self.workflow.finally)
+            .any(|step| step.output.as_deref() == Some(name))
+    }
+}
+
+impl fmt::Display for ExecutionPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Plan for {} ({})", self.workflow_name, self.workflow_path)?;
+        if let Some(timeout) = self.timeout_seconds {
+            writeln!(f, "Deadline: {}s", timeout)?;
+        }
+        for (name, stages) in [("Stage", &self.stages), ("Finally stage", &self.finally)] {
+            for (index, stage) in stages.iter().enumerate() {
+                writeln!(f, "\n{} {}:", name, index + 1)?;
+                for step in stage {
+                    write_step(f, step, "  ")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_step(f: &mut fmt::Formatter<'_>, step: &PlannedStep, indent: &str) -> fmt::Result {
+    let decision = match step.decision {
+        PlanDecision::Run => "run",
+        PlanDecision::Skip => "skip",
+        PlanDecision::Conditional => "maybe",
+    };
+    write!(f, "{}[{}] {} ({})", indent, decision, step.id, step.name)?;
+    if let Some(reason) = &step.reason {
+        write!(f, ": {}", reason)?;
+    }
+    writeln!(f)?;
+    if step.instances.is_empty() {
+        writeln!(f, "{}    $ {}", indent, step.command)?;
+    }
+    if let Some(working_dir) = &step.working_dir {
+        writeln!(f, "{}    in {}", indent, working_dir)?;
+    }
+    for warning in &step.warnings {
+        writeln!(f, "{}    warning: {}", indent, warning)?;
+    }
+    for instance in &step.instances {
+        write_step(f, instance, &format!("{}    ", indent))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use anyhow::Result;
+use std::{process::Output, time::Duration};
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::warn;
+
+/// Returned when a step's processes were stopped before they finished.
+#[derive(Debug, thiserror::Error)]
+#[error("Step terminated: {reason}")]
+pub struct Terminated {
+    pub reason: String,
+}
+
+/// A spawned command's process group. Dropping it before the command finished
+/// (because the step timed out or its future was cancelled) sends SIGTERM to the
+/// whole group and SIGKILL once the grace period has passed.
+struct ProcessGroup {
+    pgid: libc::pid_t,
+    grace_period: Duration,
+    finished: bool,
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        warn!("Terminating process group {}", self.pgid);
+        signal_group(self.pgid, libc::SIGTERM);
+
+        let pgid = self.pgid;
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                let grace_period = self.grace_period;
+                handle.spawn(async move {
+                    tokio::time::sleep(grace_period).await;
+                    signal_group(pgid, libc::SIGKILL);
+                });
+            }
+            Err(_) => signal_group(pgid, libc::SIGKILL),
+        }
+    }
+}
+
+fn signal_group(pgid: libc::pid_t, signal: libc::c_int) {
+    // SAFETY: kill(2) has no memory-safety preconditions; a negative pid targets the group
+    // and fails harmlessly with ESRCH once every process in it has exited
+    unsafe {
+        libc::kill(-pgid, signal);
+    }
+}
+
+/// Runs `command` to completion in its own process group without blocking the
+/// runtime, feeding it `stdin` and capturing stdout and stderr.
+pub async fn run(mut command: Command, stdin: Option<String>, grace_period: Duration) -> Result<Output> {
+    command.process_group(0);
+
+    let mut child = command.spawn()?;
+    let mut group = child.id().map(|pid| ProcessGroup {
+        pgid: pid as libc::pid_t,
+        grace_period,
+        finished: false,
+    });
+
+    if let (Some(mut pipe), Some(input)) = (child.stdin.take(), stdin) {
+        // Written concurrently so a child that fills its stdout first cannot deadlock us;
+        // a child that exits without reading everything just closes the pipe
+        tokio::spawn(async move {
+            let _ = pipe.write_all(input.as_bytes()).await;
+        });
+    }
+
+    let output = child.wait_with_output().await?;
+
+    if let Some(group) = group.as_mut() {
+        group.finished = true;
+    }
+    Ok(output)
+}
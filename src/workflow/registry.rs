@@ -0,0 +1,169 @@
+use super::{format, ValidationIssue, Workflow, WorkflowEngine};
+use anyhow::Result;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+use tracing::warn;
+use uuid::Uuid;
+
+/// One version of a workflow found in `workflow_dir`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisteredWorkflow {
+    pub id: Uuid,
+    pub name: String,
+    pub version: Version,
+    pub description: Option<String>,
+    pub path: PathBuf,
+    /// See `Workflow::content_hash`.
+    pub content_hash: String,
+}
+
+/// The workflows in `workflow_dir`, indexed by name and semver version, so they
+/// can be run by name rather than by file. Files that cannot be registered are
+/// kept in `issues` instead of being dropped silently.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorkflowRegistry {
+    /// Every version of each workflow, oldest first.
+    pub workflows: BTreeMap<String, Vec<RegisteredWorkflow>>,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl WorkflowRegistry {
+    /// Versions of `name`, oldest first.
+    pub fn versions(&self, name: &str) -> &[RegisteredWorkflow] {
+        self.workflows.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    /// The highest version of `name`. Pre-releases are only picked when there
+    /// is no release.
+    pub fn latest(&self, name: &str) -> Option<&RegisteredWorkflow> {
+        let versions = self.versions(name);
+        versions
+            .iter()
+            .rev()
+            .find(|workflow| workflow.version.pre.is_empty())
+            .or_else(|| versions.last())
+    }
+
+    pub fn get(&self, name: &str, version: &Version) -> Option<&RegisteredWorkflow> {
+        self.versions(name).iter().find(|workflow| &workflow.version == version)
+    }
+
+    /// Looks up `name` (the latest version) or `name@version` (that exact version).
+    pub fn find(&self, reference: &str) -> Result<&RegisteredWorkflow> {
+        let (name, version) = match reference.rsplit_once('@') {
+            Some((name, version)) => {
+                let version = Version::parse(version)
+                    .map_err(|e| anyhow::anyhow!("Invalid version in '{}': {}", reference, e))?;
+                (name, Some(version))
+            }
+            None => (reference, None),
+        };
+        if self.versions(name).is_empty() {
+            return Err(anyhow::anyhow!("No workflow named '{}' in the registry", name));
+        }
+        match version {
+            Some(version) => self.get(name, &version).ok_or_else(|| {
+                let available: Vec<String> = self.versions(name).iter().map(|w| w.version.to_string()).collect();
+                anyhow::anyhow!(
+                    "Workflow '{}' has no version {}; available: {}",
+                    name,
+                    version,
+                    available.join(", ")
+                )
+            }),
+            None => Ok(self.latest(name).expect("name has versions")),
+        }
+    }
+
+    /// Every registered workflow version, by name and then version.
+    pub fn iter(&self) -> impl Iterator<Item = &RegisteredWorkflow> {
+        self.workflows.values().flatten()
+    }
+
+    fn register(&mut self, workflow: &Workflow, path: PathBuf) -> Result<(), String> {
+        let version = Version::parse(&workflow.version)
+            .map_err(|e| format!("version '{}' is not a semver version: {}", workflow.version, e))?;
+        if let Some(existing) = self.get(&workflow.name, &version) {
+            return Err(format!(
+                "'{}' version {} is already defined in {}",
+                workflow.name,
+                version,
+                existing.path.display()
+            ));
+        }
+        let content_hash = workflow.content_hash().map_err(|e| e.to_string())?;
+        let versions = self.workflows.entry(workflow.name.clone()).or_default();
+        versions.push(RegisteredWorkflow {
+            id: workflow.id,
+            name: workflow.name.clone(),
+            version,
+            description: workflow.description.clone(),
+            path,
+            content_hash,
+        });
+        versions.sort_by(|a, b| a.version.cmp(&b.version));
+        Ok(())
+    }
+}
+
+impl WorkflowEngine {
+    /// Builds the registry of every workflow file in `workflow_dir`. Unreadable
+    /// files, invalid versions and duplicate versions end up in `issues`; an id
+    /// shared by files that are not versions of one workflow is warned about.
+    pub async fn registry(&self) -> Result<WorkflowRegistry> {
+        let mut registry = WorkflowRegistry::default();
+        let mut ids: HashMap<Uuid, (String, PathBuf)> = HashMap::new();
+
+        for path in self.workflow_files()? {
+            let file = path.display().to_string();
+            let issue = |message: String| ValidationIssue { file: file.clone(), location: String::new(), message };
+            let workflow = match format::read_workflow(&path) {
+                Ok(workflow) => workflow,
+                Err(e) => {
+                    registry.issues.push(issue(e.to_string()));
+                    continue;
+                }
+            };
+            if let Err(message) = registry.register(&workflow, path.clone()) {
+                warn!("Not registering {}: {}", file, message);
+                registry.issues.push(issue(message));
+                continue;
+            }
+            match ids.get(&workflow.id) {
+                Some((name, other)) if *name != workflow.name => {
+                    warn!(
+                        "Workflow id {} is used by both {} and {}",
+                        workflow.id,
+                        other.display(),
+                        path.display()
+                    );
+                    registry.issues.push(issue(format!(
+                        "id {} is also used by '{}' in {}",
+                        workflow.id,
+                        name,
+                        other.display()
+                    )));
+                }
+                Some(_) => {}
+                None => {
+                    ids.insert(workflow.id, (workflow.name.clone(), path));
+                }
+            }
+        }
+        Ok(registry)
+    }
+
+    /// The file a workflow reference names: an existing path is used as is,
+    /// anything else is looked up in the registry as `name` or `name@version`.
+    pub async fn resolve_workflow(&self, reference: &str) -> Result<String> {
+        if std::path::Path::new(reference).is_file() {
+            return Ok(reference.to_string());
+        }
+        let registry = self.registry().await?;
+        Ok(registry.find(reference)?.path.display().to_string())
+    }
+}
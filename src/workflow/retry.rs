@@ -0,0 +1,15 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// One try at running a step, kept in `StepExecution.attempts`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepAttempt {
+    pub attempt: u32,
+    pub started_at: DateTime<Utc>,
+    pub completed_at: DateTime<Utc>,
+    pub exit_code: Option<i32>,
+    pub error_message: Option<String>,
+    /// Time waited after this attempt before the next one, if there was one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_delay_ms: Option<u64>,
+}
@@ -0,0 +1,243 @@
+use super::{interpolation, StepExecution, Workflow};
+use crate::config::WorkflowConfig;
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+const REDACTED: &str = "***";
+const KDF_ROUNDS: u32 = 100_000;
+
+/// Where a secret's value comes from, e.g. `{"env": "DEPLOY_TOKEN"}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretSource {
+    /// An environment variable of the orchestrator process.
+    Env(String),
+    /// A file holding only the value; a trailing newline is dropped.
+    File(String),
+    /// An entry of the encrypted `WorkflowConfig.secrets_file`.
+    Encrypted(String),
+}
+
+/// The secret values of one run. They are never serialized, `Debug` shows only
+/// their names, and `redact` replaces them with `***`.
+#[derive(Clone, Default)]
+pub struct Secrets {
+    values: BTreeMap<String, String>,
+    /// Values that are redacted but not exported to steps, e.g. a parent's
+    /// secrets in a sub-workflow.
+    redacted_only: Vec<String>,
+}
+
+/// On-disk form of the encrypted secrets file: a JSON object of names to
+/// values, sealed with ChaCha20-Poly1305 under a PBKDF2-derived key.
+#[derive(Debug, Serialize, Deserialize)]
+struct SealedSecrets {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.values.keys()).finish()
+    }
+}
+
+impl Secrets {
+    /// Whether the run has no secrets to export to its steps.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.values.iter()
+    }
+
+    /// Redacts `other`'s values too without exporting them, e.g. a parent's
+    /// secrets that reach a sub-workflow through its inputs.
+    pub fn redact_also(&mut self, other: &Secrets) {
+        self.redacted_only
+            .extend(other.values.values().chain(&other.redacted_only).cloned());
+    }
+
+    /// Replaces every secret value in `text` with `***`.
+    pub fn redact(&self, text: &str) -> String {
+        self.redact_forms(text, |value| value.to_string())
+    }
+
+    /// Like `redact`, for serialized JSON where values appear escaped.
+    pub fn redact_json(&self, json: &str) -> String {
+        self.redact_forms(json, |value| {
+            let quoted = serde_json::Value::String(value.to_string()).to_string();
+            quoted[1..quoted.len() - 1].to_string()
+        })
+    }
+
+    /// Redacts everything a step produced before it reaches later steps, logs
+    /// or the execution record.
+    pub(super) fn redact_step(&self, step_execution: &mut StepExecution) {
+        if self.is_empty() && self.redacted_only.is_empty() {
+            return;
+        }
+        for text in [
+            &mut step_execution.output,
+            &mut step_execution.error_message,
+            &mut step_execution.termination_reason,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *text = self.redact(text);
+        }
+        for attempt in &mut step_execution.attempts {
+            if let Some(error_message) = &mut attempt.error_message {
+                *error_message = self.redact(error_message);
+            }
+        }
+        for value in step_execution.variables.values_mut() {
+            *value = self.redact(value);
+        }
+    }
+
+    fn redact_forms(&self, text: &str, form: impl Fn(&str) -> String) -> String {
+        // Longest first, so a secret containing another is not partially revealed
+        let mut forms: Vec<String> = self
+            .values
+            .values()
+            .chain(&self.redacted_only)
+            .filter(|value| !value.is_empty())
+            .map(|value| form(value))
+            .collect();
+        forms.sort_by_key(|form| std::cmp::Reverse(form.len()));
+
+        let mut redacted = text.to_string();
+        for form in forms {
+            redacted = redacted.replace(&form, REDACTED);
+        }
+        redacted
+    }
+}
+
+/// Checks secret declarations when the workflow is loaded.
+pub fn validate_declarations(workflow: &Workflow) -> Result<()> {
+    for name in workflow.secrets.keys() {
+        if !interpolation::is_valid_name(name) {
+            return Err(anyhow::anyhow!("Invalid secret name '{}'", name));
+        }
+        if workflow.variables.contains_key(name) || workflow.inputs.contains_key(name) {
+            return Err(anyhow::anyhow!("Secret {} has the same name as a variable or input", name));
+        }
+    }
+    Ok(())
+}
+
+/// Reads every secret the workflow declares. The encrypted secrets file is only
+/// opened when a secret refers to it.
+pub fn resolve(workflow: &Workflow, config: &WorkflowConfig) -> Result<Secrets> {
+    let mut values = BTreeMap::new();
+    let mut sealed: Option<BTreeMap<String, String>> = None;
+
+    for (name, source) in &workflow.secrets {
+        let value = match source {
+            SecretSource::Env(variable) => std::env::var(variable).map_err(|_| {
+                anyhow::anyhow!("Secret {} needs environment variable {}, which is not set", name, variable)
+            })?,
+            SecretSource::File(path) => fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Cannot read secret {} from {}: {}", name, path, e))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+            SecretSource::Encrypted(key) => {
+                if sealed.is_none() {
+                    sealed = Some(open_secrets_file(config)?);
+                }
+                sealed
+                    .as_ref()
+                    .and_then(|sealed| sealed.get(key.as_str()))
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("Secret {} is not in the encrypted secrets file", key))?
+            }
+        };
+        values.insert(name.clone(), value);
+    }
+
+    Ok(Secrets { values, redacted_only: Vec::new() })
+}
+
+/// Encrypts a plain JSON object of secret values from `plaintext_path` into the
+/// configured `secrets_file`, using the passphrase in `secrets_key_env`.
+pub fn seal_file(plaintext_path: &Path, config: &WorkflowConfig) -> Result<()> {
+    let values: BTreeMap<String, String> = serde_json::from_str(&fs::read_to_string(plaintext_path)?)
+        .map_err(|e| anyhow::anyhow!("{} must be a JSON object of strings: {}", plaintext_path.display(), e))?;
+    let sealed = seal(&values, &passphrase(config)?)?;
+    fs::write(secrets_file(config)?, sealed)?;
+    Ok(())
+}
+
+/// Encrypts `values` with a key derived from `passphrase`.
+pub fn seal(values: &BTreeMap<String, String>, passphrase: &str) -> Result<String> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher(passphrase, &salt)
+        .encrypt(&nonce, serde_json::to_vec(values)?.as_slice())
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt secrets"))?;
+
+    Ok(serde_json::to_string_pretty(&SealedSecrets {
+        version: 1,
+        salt: BASE64.encode(salt),
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    })?)
+}
+
+/// Decrypts the output of `seal`.
+pub fn unseal(content: &str, passphrase: &str) -> Result<BTreeMap<String, String>> {
+    let sealed: SealedSecrets = serde_json::from_str(content)?;
+    if sealed.version != 1 {
+        return Err(anyhow::anyhow!("Unsupported secrets file version {}", sealed.version));
+    }
+    let nonce = BASE64.decode(&sealed.nonce)?;
+    if nonce.len() != 12 {
+        return Err(anyhow::anyhow!("Secrets file has a malformed nonce"));
+    }
+
+    let plaintext = cipher(passphrase, &BASE64.decode(&sealed.salt)?)
+        .decrypt(Nonce::from_slice(&nonce), BASE64.decode(&sealed.ciphertext)?.as_slice())
+        .map_err(|_| anyhow::anyhow!("Cannot decrypt secrets file: wrong key or corrupted file"))?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> ChaCha20Poly1305 {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, KDF_ROUNDS, &mut key);
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+fn open_secrets_file(config: &WorkflowConfig) -> Result<BTreeMap<String, String>> {
+    let path = secrets_file(config)?;
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Cannot read secrets file {}: {}", path.display(), e))?;
+    unseal(&content, &passphrase(config)?)
+}
+
+fn secrets_file(config: &WorkflowConfig) -> Result<&Path> {
+    config
+        .secrets_file
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("No secrets_file is configured"))
+}
+
+fn passphrase(config: &WorkflowConfig) -> Result<String> {
+    std::env::var(&config.secrets_key_env).map_err(|_| {
+        anyhow::anyhow!("Set {} to the secrets file passphrase", config.secrets_key_env)
+    })
+}
@@ -0,0 +1,212 @@
+use super::{StepOutput, WorkflowEngine, WorkflowExecution, WorkflowStep};
+use anyhow::Result;
+use chrono::{DateTime, Local, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path, time::Duration};
+use tracing::{debug, info};
+
+/// Polling settings of a `Wait` step. How long it may wait is the step's
+/// `timeout`, like any other step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaitSpec {
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// For `file_stable`: how long the file must go unchanged; defaults to one poll interval.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stable_for_ms: Option<u64>,
+}
+
+fn default_poll_interval_ms() -> u64 {
+    5000
+}
+
+impl Default for WaitSpec {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: default_poll_interval_ms(),
+            stable_for_ms: None,
+        }
+    }
+}
+
+/// The `command`s of `Wait` steps.
+pub(super) const SENSORS: &[&str] = &["file", "file_stable", "glob", "command", "port", "duration", "until"];
+
+/// What a `Wait` step waits for, chosen by its `command`.
+enum Sensor<'a> {
+    /// `file`: `args[0]` exists.
+    File(&'a Path),
+    /// `file_stable`: `args[0]` exists and its size and modification time stop changing.
+    FileStable(&'a Path),
+    /// `glob`: the pattern `args[0]` matches at least `args[1]` files (default 1).
+    Glob(&'a str, usize),
+    /// `command`: the program `args[0]`, run with the remaining args, exits 0.
+    Command(&'a str, &'a [String]),
+    /// `port`: `args[0]` (`host:port`) accepts TCP connections.
+    Port(&'a str),
+    /// `duration`: `args[0]` seconds have passed.
+    Duration(Duration),
+    /// `until`: the wall-clock time `args[0]` (RFC 3339, or `HH:MM[:SS]` local time) has passed.
+    Until(DateTime<Utc>),
+}
+
+impl<'a> Sensor<'a> {
+    fn parse(step: &'a WorkflowStep) -> Result<Self> {
+        let operand = |index: usize, what: &str| {
+            step.args
+                .get(index)
+                .map(String::as_str)
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| anyhow::anyhow!("Step {} is missing its {} (argument {})", step.id, what, index + 1))
+        };
+        Ok(match step.command.as_str() {
+            "file" => Self::File(Path::new(operand(0, "file path")?)),
+            "file_stable" => Self::FileStable(Path::new(operand(0, "file path")?)),
+            "glob" => {
+                let count = match step.args.get(1) {
+                    Some(count) => count
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid file count '{}' in step {}", count, step.id))?,
+                    None => 1,
+                };
+                Self::Glob(operand(0, "glob pattern")?, count)
+            }
+            "command" => Self::Command(operand(0, "program")?, &step.args[1..]),
+            "port" => Self::Port(operand(0, "host:port address")?),
+            "duration" => {
+                let seconds = operand(0, "number of seconds")?;
+                let seconds: f64 = seconds
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid duration '{}' in step {}", seconds, step.id))?;
+                Self::Duration(Duration::try_from_secs_f64(seconds)?)
+            }
+            "until" => Self::Until(parse_time(operand(0, "time")?)?),
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Unknown wait '{}' in step {}; expected one of {}",
+                    other,
+                    step.id,
+                    SENSORS.join(", ")
+                ))
+            }
+        })
+    }
+}
+
+/// An RFC 3339 timestamp, or the next occurrence of a local `HH:MM[:SS]` time.
+fn parse_time(time: &str) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(time) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    let clock = NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .map_err(|_| anyhow::anyhow!("Invalid time '{}'; expected RFC 3339 or HH:MM[:SS]", time))?;
+    let now = Local::now();
+    let mut date = now.date_naive();
+    if clock <= now.time() {
+        date = date.succ_opt().unwrap_or(date);
+    }
+    Local
+        .from_local_datetime(&date.and_time(clock))
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| anyhow::anyhow!("Time '{}' does not exist today", time))
+}
+
+impl WorkflowEngine {
+    /// Polls until the step's sensor is satisfied. The step holds no process slot
+    /// while it waits; a `command` sensor takes one only while its probe runs.
+    /// The step's timeout ends the wait.
+    pub(super) async fn wait_step(&self, step: &WorkflowStep, execution: &WorkflowExecution) -> Result<StepOutput> {
+        let sensor = Sensor::parse(step)?;
+        let spec = step.wait.clone().unwrap_or_default();
+        let poll_interval = Duration::from_millis(spec.poll_interval_ms.max(1));
+        let started = tokio::time::Instant::now();
+        let mut variables = HashMap::new();
+        let mut polls = 0u64;
+
+        let message = match sensor {
+            Sensor::Duration(duration) => {
+                tokio::time::sleep(duration).await;
+                format!("waited {:.1}s", duration.as_secs_f64())
+            }
+            Sensor::Until(time) => {
+                tokio::time::sleep((time - Utc::now()).to_std().unwrap_or_default()).await;
+                format!("reached {}", time.to_rfc3339())
+            }
+            Sensor::File(path) => {
+                while !path.exists() {
+                    polls += 1;
+                    tokio::time::sleep(poll_interval).await;
+                }
+                format!("{} exists", path.display())
+            }
+            Sensor::FileStable(path) => {
+                let stable_for = spec.stable_for_ms.map(Duration::from_millis).unwrap_or(poll_interval);
+                let mut last = None;
+                let mut unchanged_since = tokio::time::Instant::now();
+                loop {
+                    let current = fs::metadata(path).ok().map(|metadata| (metadata.len(), metadata.modified().ok()));
+                    if current.is_none() || current != last {
+                        last = current;
+                        unchanged_since = tokio::time::Instant::now();
+                    } else if unchanged_since.elapsed() >= stable_for {
+                        break;
+                    }
+                    polls += 1;
+                    tokio::time::sleep(poll_interval).await;
+                }
+                format!("{} is stable", path.display())
+            }
+            Sensor::Glob(pattern, count) => {
+                let files = loop {
+                    let files: Vec<String> = glob::glob(pattern)
+                        .map_err(|e| anyhow::anyhow!("Invalid glob '{}' in step {}: {}", pattern, step.id, e))?
+                        .filter_map(|path| path.ok())
+                        .map(|path| path.display().to_string())
+                        .collect();
+                    if files.len() >= count {
+                        break files;
+                    }
+                    polls += 1;
+                    tokio::time::sleep(poll_interval).await;
+                };
+                variables.insert("count".to_string(), files.len().to_string());
+                variables.insert("files".to_string(), files.join("\n"));
+                format!("{} matches {} file(s)", pattern, files.len())
+            }
+            Sensor::Command(program, args) => {
+                loop {
+                    let output = {
+                        let _permit = self.step_slots.acquire().await?;
+                        self.run_command(step, program, args, execution).await?
+                    };
+                    if output.success() {
+                        break;
+                    }
+                    debug!("Wait step {} probe exited with {:?}", step.id, output.exit_code);
+                    polls += 1;
+                    tokio::time::sleep(poll_interval).await;
+                }
+                format!("{} succeeded", program)
+            }
+            Sensor::Port(address) => {
+                loop {
+                    let connect = tokio::net::TcpStream::connect(address);
+                    if let Ok(Ok(_)) = tokio::time::timeout(poll_interval, connect).await {
+                        break;
+                    }
+                    polls += 1;
+                    tokio::time::sleep(poll_interval).await;
+                }
+                format!("{} accepts connections", address)
+            }
+        };
+
+        let waited = started.elapsed().as_secs_f64();
+        info!("Wait step {} done after {:.1}s: {}", step.id, waited, message);
+        variables.insert("waited_seconds".to_string(), format!("{:.1}", waited));
+        variables.insert("polls".to_string(), polls.to_string());
+        Ok(StepOutput::builtin(message, variables))
+    }
+}
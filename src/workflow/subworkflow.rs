@@ -0,0 +1,154 @@
+use super::{
+    format,
+    interpolation::{self, Segment},
+    StepOutput, StepType, Workflow, WorkflowEngine, WorkflowExecution, WorkflowStep,
+};
+use anyhow::Result;
+use futures::future::{BoxFuture, FutureExt};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::info;
+
+/// `Workflow` steps run another workflow file as a child execution. The step's
+/// `inputs` are checked against the child's declared inputs; the child
+/// variables named in `outputs` are published under the step, along with
+/// `execution_id` and `status`. The child's record points back at the parent
+/// through `parent_execution_id` and the parent's step records `child_execution_id`.
+impl WorkflowEngine {
+    /// Boxed because the child run can itself contain `Workflow` steps.
+    pub(super) fn sub_workflow_step<'a>(
+        &'a self,
+        step: &'a WorkflowStep,
+        execution: &'a WorkflowExecution,
+    ) -> BoxFuture<'a, Result<StepOutput>> {
+        self.run_sub_workflow(step, execution).boxed()
+    }
+
+    async fn run_sub_workflow(&self, step: &WorkflowStep, execution: &WorkflowExecution) -> Result<StepOutput> {
+        if step.command.trim().is_empty() {
+            return Err(anyhow::anyhow!("Step {} does not name a workflow file", step.id));
+        }
+        let path = self.sub_workflow_path(&step.command);
+        let canonical = fs::canonicalize(&path)
+            .map_err(|e| anyhow::anyhow!("Sub-workflow {} not found: {}", path.display(), e))?;
+
+        // References resolved from variables can only be checked against the
+        // executions that are actually running
+        let mut chain = vec![canonical.clone()];
+        let mut ancestor = Some(execution.clone());
+        while let Some(current) = ancestor {
+            if let Some(workflow_path) = &current.workflow_path {
+                let workflow_path = PathBuf::from(workflow_path);
+                chain.push(workflow_path.clone());
+                if workflow_path == canonical {
+                    chain.reverse();
+                    return Err(cycle_error(&chain));
+                }
+            }
+            ancestor = match current.parent_execution_id {
+                Some(parent_id) => self.get_execution(parent_id).await?,
+                None => None,
+            };
+        }
+
+        let workflow_path = canonical.display().to_string();
+        let workflow = self.load_workflow(&workflow_path).await?;
+        let inputs: HashMap<String, String> = step
+            .inputs
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        // Children without an `inputs` block take any mapped name as a variable
+        let mut child = self.new_execution(&workflow, &workflow_path, &inputs, true)?;
+        child.parent_execution_id = Some(execution.id);
+        // Inputs may carry the parent's secrets, so the child redacts them too;
+        // they are not exported to its steps unless it declares them itself
+        child.secrets.redact_also(&execution.secrets);
+        let child_id = child.id;
+        info!("Step {} starts sub-workflow execution {} ({})", step.id, child_id, workflow.name);
+
+        let child = match self.run_execution(&workflow, child).await {
+            Ok(child) => child,
+            Err(e) => {
+                return Ok(StepOutput {
+                    stdout: String::new(),
+                    stderr: format!("Sub-workflow execution {} failed: {}", child_id, e),
+                    exit_code: None,
+                    variables: HashMap::new(),
+                    child_execution_id: Some(child_id),
+                    approval: None,
+                });
+            }
+        };
+
+        let mut variables = HashMap::new();
+        for (name, child_variable) in &step.outputs {
+            let value = child.variables.get(child_variable).ok_or_else(|| {
+                anyhow::anyhow!("Sub-workflow execution {} did not set '{}'", child_id, child_variable)
+            })?;
+            variables.insert(name.clone(), value.clone());
+        }
+        variables.insert("execution_id".to_string(), child_id.to_string());
+        variables.insert("status".to_string(), child.status.to_string());
+
+        Ok(StepOutput {
+            child_execution_id: Some(child_id),
+            ..StepOutput::builtin(child_id.to_string(), variables)
+        })
+    }
+
+    /// Follows `Workflow` steps with fixed file names through the files they
+    /// reference, rejecting any chain that leads back to a workflow in `stack`.
+    pub(super) fn check_sub_workflow_cycles(&self, workflow: &Workflow, stack: &mut Vec<PathBuf>) -> Result<()> {
+        for step in workflow.steps.iter().chain(&workflow.finally) {
+            if !matches!(step.step_type, StepType::Workflow) || !is_literal(&step.command) {
+                continue;
+            }
+
+            let path = self.sub_workflow_path(&step.command);
+            let canonical = fs::canonicalize(&path).map_err(|e| {
+                anyhow::anyhow!("Step {} references sub-workflow {}: {}", step.id, path.display(), e)
+            })?;
+            if stack.contains(&canonical) {
+                stack.push(canonical);
+                return Err(cycle_error(stack));
+            }
+
+            let child = format::read_workflow(&canonical)?;
+            stack.push(canonical);
+            self.check_sub_workflow_cycles(&child, stack)?;
+            stack.pop();
+        }
+        Ok(())
+    }
+
+    fn sub_workflow_path(&self, reference: &str) -> PathBuf {
+        let path = Path::new(reference);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.config.workflow_dir.join(path)
+        }
+    }
+}
+
+fn is_literal(template: &str) -> bool {
+    interpolation::parse(template)
+        .map(|segments| segments.iter().all(|segment| matches!(segment, Segment::Literal(_))))
+        .unwrap_or(false)
+}
+
+fn cycle_error(chain: &[PathBuf]) -> anyhow::Error {
+    let chain: Vec<String> = chain
+        .iter()
+        .map(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.display().to_string())
+        })
+        .collect();
+    anyhow::anyhow!("Circular sub-workflow reference: {}", chain.join(" -> "))
+}
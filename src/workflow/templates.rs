@@ -0,0 +1,180 @@
+use super::WorkflowFormat;
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Fields a file named in `include` may define.
+const INCLUDABLE_FIELDS: &[&str] = &["include", "templates", "variables", "steps", "finally"];
+
+/// Whether a workflow document uses `include`, `templates` or `uses`, and so
+/// has to be resolved before it deserializes into a `Workflow`.
+pub fn is_composed(document: &Value) -> bool {
+    let uses_template = |list: &str| {
+        document
+            .get(list)
+            .and_then(Value::as_array)
+            .is_some_and(|steps| steps.iter().any(|step| step.get("uses").is_some()))
+    };
+    document.get("include").is_some()
+        || document.get("templates").is_some()
+        || uses_template("steps")
+        || uses_template("finally")
+}
+
+/// Resolves a workflow document read from `path` into the plain form `Workflow`
+/// deserializes from:
+///
+/// - `include` lists files, relative to the including one, whose `steps` and
+///   `finally` steps are added before the workflow's own and whose `variables`
+///   and `templates` are merged in, the including file's taking precedence.
+///   Included files may include others, so shared libraries can live in a
+///   subdirectory of `workflow_dir`.
+/// - `templates` maps names to partial steps. A step with `uses: <name>` starts
+///   from that template and overrides any of its fields; templates may `uses`
+///   another template.
+pub fn resolve(document: Value, path: &Path) -> Result<Value> {
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut document = resolve_file(document, path, &mut vec![canonical])?;
+    if let Some(object) = document.as_object_mut() {
+        object.remove("templates");
+    }
+    Ok(document)
+}
+
+fn resolve_file(document: Value, path: &Path, chain: &mut Vec<PathBuf>) -> Result<Value> {
+    let Value::Object(mut document) = document else {
+        return Err(anyhow::anyhow!("a workflow must be an object of fields"));
+    };
+
+    let mut templates = Map::new();
+    let mut variables = Map::new();
+    let mut steps = Vec::new();
+    let mut finally = Vec::new();
+    if let Some(include) = document.remove("include") {
+        let Value::Array(include) = include else {
+            return Err(anyhow::anyhow!("include: expected a list of files"));
+        };
+        let base = path.parent().unwrap_or(Path::new("."));
+        for (index, file) in include.iter().enumerate() {
+            let file = file
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("include[{}]: expected a file path", index))?;
+            let mut included = include_file(&base.join(file), chain)?;
+            templates.append(&mut take_object(&mut included, "templates")?);
+            variables.append(&mut take_object(&mut included, "variables")?);
+            steps.append(&mut take_array(&mut included, "steps")?);
+            finally.append(&mut take_array(&mut included, "finally")?);
+        }
+    }
+
+    templates.append(&mut take_object(&mut document, "templates")?);
+    for (list, mut steps) in [("steps", steps), ("finally", finally)] {
+        let declared = document.contains_key(list);
+        let mut own = take_array(&mut document, list)?;
+        for (index, step) in own.iter_mut().enumerate() {
+            apply_template(step, &templates).map_err(|e| anyhow::anyhow!("{}[{}].uses: {}", list, index, e))?;
+        }
+        steps.append(&mut own);
+        if declared || !steps.is_empty() {
+            document.insert(list.to_string(), Value::Array(steps));
+        }
+    }
+
+    let declared = document.contains_key("variables");
+    variables.append(&mut take_object(&mut document, "variables")?);
+    if declared || !variables.is_empty() {
+        document.insert("variables".to_string(), Value::Object(variables));
+    }
+    if !templates.is_empty() {
+        document.insert("templates".to_string(), Value::Object(templates));
+    }
+    Ok(Value::Object(document))
+}
+
+/// Reads and resolves a file named in `include`.
+fn include_file(path: &Path, chain: &mut Vec<PathBuf>) -> Result<Map<String, Value>> {
+    let canonical = fs::canonicalize(path)
+        .map_err(|e| anyhow::anyhow!("Included file {} not found: {}", path.display(), e))?;
+    if chain.contains(&canonical) {
+        chain.push(canonical);
+        let names: Vec<String> = chain
+            .iter()
+            .map(|path| path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().to_string())
+            .collect();
+        return Err(anyhow::anyhow!("Circular include: {}", names.join(" -> ")));
+    }
+
+    let format = WorkflowFormat::of(path)?;
+    let content = fs::read_to_string(path)?;
+    let document: Value = format
+        .parse(&content)
+        .map_err(|e| anyhow::anyhow!("Invalid {} file {}: {}", format, path.display(), e))?;
+    if let Some(field) = document
+        .as_object()
+        .and_then(|object| object.keys().find(|key| !INCLUDABLE_FIELDS.contains(&key.as_str())))
+    {
+        return Err(anyhow::anyhow!(
+            "Included file {} defines '{}'; it may only define {}",
+            path.display(),
+            field,
+            INCLUDABLE_FIELDS.join(", ")
+        ));
+    }
+
+    chain.push(canonical);
+    let resolved = resolve_file(document, path, chain)
+        .map_err(|e| anyhow::anyhow!("In included file {}: {}", path.display(), e));
+    chain.pop();
+    match resolved? {
+        Value::Object(object) => Ok(object),
+        _ => unreachable!("resolve_file returns an object"),
+    }
+}
+
+/// Expands a step's `uses`, following templates that use other templates.
+fn apply_template(step: &mut Value, templates: &Map<String, Value>) -> Result<()> {
+    let Some(step) = step.as_object_mut() else {
+        return Ok(());
+    };
+    let mut used = Vec::new();
+    while let Some(name) = step.remove("uses") {
+        let name = name
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("expected a template name"))?
+            .to_string();
+        if used.contains(&name) {
+            used.push(name);
+            return Err(anyhow::anyhow!("circular step template: {}", used.join(" -> ")));
+        }
+        let template = templates
+            .get(&name)
+            .ok_or_else(|| anyhow::anyhow!("unknown step template '{}'", name))?
+            .as_object()
+            .ok_or_else(|| anyhow::anyhow!("step template '{}' must be an object of step fields", name))?;
+        // The step's own fields win over the template's
+        for (field, value) in template {
+            step.entry(field.clone()).or_insert_with(|| value.clone());
+        }
+        used.push(name);
+    }
+    Ok(())
+}
+
+fn take_object(object: &mut Map<String, Value>, field: &str) -> Result<Map<String, Value>> {
+    match object.remove(field) {
+        None => Ok(Map::new()),
+        Some(Value::Object(value)) => Ok(value),
+        Some(_) => Err(anyhow::anyhow!("{}: expected an object", field)),
+    }
+}
+
+fn take_array(object: &mut Map<String, Value>, field: &str) -> Result<Vec<Value>> {
+    match object.remove(field) {
+        None => Ok(Vec::new()),
+        Some(Value::Array(value)) => Ok(value),
+        Some(_) => Err(anyhow::anyhow!("{}: expected a list", field)),
+    }
+}
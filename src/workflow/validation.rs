@@ -0,0 +1,383 @@
+use super::{
+    expansion, inputs,
+    interpolation::{self, Segment},
+    secrets, sensor, templates, Expr, StepType, Templated, Workflow, WorkflowEngine, WorkflowFormat, WorkflowStep,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+/// Fields a workflow file and each of its steps must have.
+const REQUIRED_WORKFLOW_FIELDS: &[&str] = &["id", "name", "version", "created_at", "steps", "variables", "metadata"];
+const REQUIRED_STEP_FIELDS: &[&str] = &["id", "name", "step_type", "args", "depends_on"];
+
+/// One problem found by `validate_workflow`, e.g.
+/// `deploy.json: steps[2].depends_on[0]: unknown step 'biuld'`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidationIssue {
+    pub file: String,
+    /// Path of the offending field, such as `steps[2].condition`; empty for the whole file.
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.location.is_empty() {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(f, "{}: {}: {}", self.file, self.location, self.message)
+        }
+    }
+}
+
+impl WorkflowEngine {
+    /// Checks a workflow file without running it and reports every problem found,
+    /// rather than stopping at the first like loading it for a run does. An empty
+    /// list means the workflow is valid.
+    pub async fn validate_workflow(&self, workflow_path: &str) -> Result<Vec<ValidationIssue>> {
+        let mut issues = Issues { file: workflow_path.to_string(), found: Vec::new() };
+        let content = fs::read_to_string(workflow_path)
+            .map_err(|e| anyhow::anyhow!("Cannot read workflow {}: {}", workflow_path, e))?;
+
+        let format = WorkflowFormat::of(Path::new(workflow_path))?;
+        let mut document: serde_json::Value = match format.parse(&content) {
+            Ok(document) => document,
+            Err(e) => {
+                issues.push("", format!("invalid {}: {}", format, e));
+                return Ok(issues.found);
+            }
+        };
+        if templates::is_composed(&document) {
+            document = match templates::resolve(document, Path::new(workflow_path)) {
+                Ok(document) => document,
+                Err(e) => {
+                    issues.push("", e.to_string());
+                    return Ok(issues.found);
+                }
+            };
+        }
+        if !check_structure(&document, &mut issues) {
+            return Ok(issues.found);
+        }
+        let workflow: Workflow = match serde_json::from_value(document) {
+            Ok(workflow) => workflow,
+            Err(e) => {
+                issues.push("", e.to_string());
+                return Ok(issues.found);
+            }
+        };
+
+        check_steps(&workflow, &mut issues);
+        for (name, result) in [
+            ("inputs", inputs::validate_declarations(&workflow)),
+            ("secrets", secrets::validate_declarations(&workflow)),
+        ] {
+            if let Err(e) = result {
+                issues.push(name, e.to_string());
+            }
+        }
+        if let Ok(path) = fs::canonicalize(workflow_path) {
+            if let Err(e) = self.check_sub_workflow_cycles(&workflow, &mut vec![path]) {
+                issues.push("steps", e.to_string());
+            }
+        }
+        Ok(issues.found)
+    }
+
+    /// Validates every workflow file in `workflow_dir`.
+    pub async fn validate_all_workflows(&self) -> Result<Vec<ValidationIssue>> {
+        let mut issues = Vec::new();
+        for path in self.workflow_files()? {
+            issues.extend(self.validate_workflow(&path.display().to_string()).await?);
+        }
+        Ok(issues)
+    }
+
+    pub(super) fn workflow_files(&self) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        if self.config.workflow_dir.exists() {
+            for entry in fs::read_dir(&self.config.workflow_dir)? {
+                let path = entry?.path();
+                if path.is_file() && WorkflowFormat::from_path(&path).is_some() {
+                    paths.push(path);
+                }
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+struct Issues {
+    file: String,
+    found: Vec<ValidationIssue>,
+}
+
+impl Issues {
+    fn push(&mut self, location: impl Into<String>, message: impl Into<String>) {
+        self.found.push(ValidationIssue {
+            file: self.file.clone(),
+            location: location.into(),
+            message: message.into(),
+        });
+    }
+}
+
+/// Reports missing required fields and steps that do not deserialize. Returns
+/// whether the whole workflow can be deserialized for the remaining checks.
+fn check_structure(document: &serde_json::Value, issues: &mut Issues) -> bool {
+    let Some(object) = document.as_object() else {
+        issues.push("", "a workflow must be an object of fields");
+        return false;
+    };
+    let before = issues.found.len();
+    for field in REQUIRED_WORKFLOW_FIELDS {
+        if !object.contains_key(*field) {
+            issues.push(*field, "missing required field");
+        }
+    }
+    for list in ["steps", "finally"] {
+        let Some(steps) = object.get(list).and_then(|steps| steps.as_array()) else {
+            continue;
+        };
+        for (index, step) in steps.iter().enumerate() {
+            let location = format!("{}[{}]", list, index);
+            let missing: Vec<&&str> = REQUIRED_STEP_FIELDS
+                .iter()
+                .filter(|field| step.get(**field).is_none())
+                .collect();
+            for field in &missing {
+                issues.push(format!("{}.{}", location, field), "missing required field");
+            }
+            if missing.is_empty() {
+                if let Err(e) = serde_json::from_value::<WorkflowStep>(step.clone()) {
+                    issues.push(location, e.to_string());
+                }
+            }
+        }
+    }
+    issues.found.len() == before
+}
+
+fn check_steps(workflow: &Workflow, issues: &mut Issues) {
+    let located: Vec<(String, &WorkflowStep)> = workflow
+        .steps
+        .iter()
+        .enumerate()
+        .map(|(index, step)| (format!("steps[{}]", index), step))
+        .chain(
+            workflow
+                .finally
+                .iter()
+                .enumerate()
+                .map(|(index, step)| (format!("finally[{}]", index), step)),
+        )
+        .collect();
+
+    let mut seen = HashSet::new();
+    for (location, step) in &located {
+        if !seen.insert(step.id.as_str()) {
+            issues.push(format!("{}.id", location), format!("duplicate step id '{}'", step.id));
+        }
+    }
+
+    for (steps, list) in [(&workflow.steps, "steps"), (&workflow.finally, "finally")] {
+        for (index, step) in steps.iter().enumerate() {
+            for (dep_index, dependency) in step.depends_on.iter().enumerate() {
+                if !steps.iter().any(|other| &other.id == dependency) {
+                    let message = if list == "finally" && workflow.steps.iter().any(|other| &other.id == dependency) {
+                        format!("finally step depends on '{}', which is not a finally step", dependency)
+                    } else {
+                        format!("unknown step '{}'", dependency)
+                    };
+                    issues.push(format!("{}[{}].depends_on[{}]", list, index, dep_index), message);
+                }
+            }
+        }
+        if let Some(cycle) = find_cycle(steps) {
+            issues.push(list, format!("dependency cycle: {}", cycle.join(" -> ")));
+        }
+    }
+
+    let known = known_variables(workflow);
+    let outputs: HashSet<&str> = workflow
+        .steps
+        .iter()
+        .chain(&workflow.finally)
+        .filter_map(|step| step.output.as_deref())
+        .collect();
+    for (location, step) in &located {
+        for (field, message) in step_problems(step, workflow) {
+            let location = if field.is_empty() { location.clone() } else { format!("{}.{}", location, field) };
+            issues.push(location, message);
+        }
+
+        let mut step_known = known.clone();
+        if let Some(for_each) = &step.for_each {
+            step_known.insert(for_each.variable.clone());
+        }
+        step_known.extend(step.matrix.keys().cloned());
+        for (field, template) in templates(step) {
+            match interpolation::parse_with(&template, |name| outputs.contains(name)) {
+                Ok(segments) => {
+                    for segment in segments {
+                        if let Segment::Variable { name, default: None } = segment {
+                            if !is_known(&name, &step_known) {
+                                issues.push(
+                                    format!("{}.{}", location, field),
+                                    format!("undefined variable '{}'", name),
+                                );
+                            }
+                        }
+                    }
+                }
+                Err(e) => issues.push(format!("{}.{}", location, field), e.to_string()),
+            }
+        }
+
+        if let Some(executable) = executable(step) {
+            if find_on_path(&executable).is_none() {
+                issues.push(
+                    format!("{}.command", location),
+                    format!("executable '{}' not found on PATH", executable),
+                );
+            }
+        }
+    }
+}
+
+/// The first dependency cycle among `steps`, as the ids along it.
+fn find_cycle(steps: &[WorkflowStep]) -> Option<Vec<String>> {
+    fn visit<'a>(
+        step: &'a WorkflowStep,
+        steps: &'a [WorkflowStep],
+        path: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+    ) -> Option<Vec<String>> {
+        if let Some(start) = path.iter().position(|id| *id == step.id) {
+            let mut cycle: Vec<String> = path[start..].iter().map(|id| id.to_string()).collect();
+            cycle.push(step.id.clone());
+            return Some(cycle);
+        }
+        if !done.insert(&step.id) {
+            return None;
+        }
+        path.push(&step.id);
+        for dependency in &step.depends_on {
+            if let Some(next) = steps.iter().find(|other| &other.id == dependency) {
+                if let Some(cycle) = visit(next, steps, path, done) {
+                    return Some(cycle);
+                }
+            }
+        }
+        path.pop();
+        None
+    }
+
+    let mut done = HashSet::new();
+    steps.iter().find_map(|step| visit(step, steps, &mut Vec::new(), &mut done))
+}
+
+/// Variables a step may reference: declared ones plus whatever steps publish.
+/// The rules a single step must follow, shared by `load_workflow`, which fails
+/// on the first problem, and `validate_workflow`, which reports them all. Each
+/// problem comes with the step field it concerns, empty for the whole step.
+pub(super) fn step_problems(step: &WorkflowStep, workflow: &Workflow) -> Vec<(&'static str, String)> {
+    let mut problems = Vec::new();
+    if let Err(e) = expansion::check_reserved_id(step, workflow.steps.iter().chain(&workflow.finally)) {
+        problems.push(("id", e.to_string()));
+    }
+    if let Some(condition) = &step.condition {
+        if let Err(e) = Expr::parse(condition) {
+            problems.push(("condition", e.to_string()));
+        }
+    }
+    if let Some(policy) = &step.retry_policy {
+        if let Err(e) = policy.validate() {
+            problems.push(("retry_policy", e.to_string()));
+        }
+    }
+    if let Err(e) = expansion::validate(step) {
+        problems.push(("", e.to_string()));
+    }
+    // A cached result would skip a child run, a gate or a wait
+    if step.cache.is_some() && (step.step_type.waits() || matches!(step.step_type, StepType::Workflow)) {
+        problems.push(("cache", format!("{:?} steps cannot be cached", step.step_type)));
+    }
+    if step.approval.is_some() && !matches!(step.step_type, StepType::Approval) {
+        problems.push(("approval", "approval settings only apply to Approval steps".to_string()));
+    }
+    if step.wait.is_some() && !matches!(step.step_type, StepType::Wait) {
+        problems.push(("wait", "wait settings only apply to Wait steps".to_string()));
+    }
+    if matches!(step.step_type, StepType::Wait) && !sensor::SENSORS.contains(&step.command.as_str()) {
+        problems.push((
+            "command",
+            format!("unknown wait '{}'; expected one of {}", step.command, sensor::SENSORS.join(", ")),
+        ));
+    }
+    problems
+}
+
+fn known_variables(workflow: &Workflow) -> HashSet<String> {
+    let mut known: HashSet<String> = workflow.variables.keys().cloned().collect();
+    known.extend(workflow.inputs.keys().cloned());
+    known.extend(workflow.secrets.keys().cloned());
+    for step in workflow.steps.iter().chain(&workflow.finally) {
+        if let Some(output) = &step.output {
+            known.insert(output.clone());
+        }
+    }
+    known
+}
+
+fn is_known(name: &str, known: &HashSet<String>) -> bool {
+    known.contains(name)
+        || name.starts_with("steps.")
+        || known.iter().any(|output| name.starts_with(&format!("{}.", output)))
+}
+
+/// Every field of `step` that is interpolated before it runs.
+fn templates(step: &WorkflowStep) -> Vec<(String, String)> {
+    let mut templates = vec![("name".to_string(), step.name.clone()), ("command".to_string(), step.command.clone())];
+    templates.extend(step.args.iter().enumerate().map(|(i, arg)| (format!("args[{}]", i), arg.clone())));
+    if let Some(Templated::Template(timeout)) = &step.timeout {
+        templates.push(("timeout".to_string(), timeout.clone()));
+    }
+    templates.extend(step.working_dir.iter().map(|dir| ("working_dir".to_string(), dir.clone())));
+    templates.extend(step.stdin.iter().map(|stdin| ("stdin".to_string(), stdin.clone())));
+    templates.extend(step.env.iter().map(|(name, value)| (format!("env.{}", name), value.clone())));
+    templates.extend(step.inputs.iter().map(|(name, value)| (format!("inputs.{}", name), value.clone())));
+    templates.extend(step.artifacts.iter().enumerate().map(|(i, path)| (format!("artifacts[{}]", i), path.clone())));
+    templates
+}
+
+/// The program a step starts, when it is fixed in the definition.
+fn executable(step: &WorkflowStep) -> Option<String> {
+    if !step.runs_program() {
+        return None;
+    }
+    // A shell step's command may be a whole script; check its first word
+    let program = if step.shell { step.command.split_whitespace().next()? } else { step.command.as_str() };
+    if program.is_empty() || program.contains('/') || program.contains('$') {
+        return None;
+    }
+    Some(program.to_string())
+}
+
+fn find_on_path(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| is_executable(candidate))
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}